@@ -0,0 +1,418 @@
+use anyhow::{anyhow, Context};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::providers::provider::Provider;
+use crate::routing::Route;
+use crate::utils::{trim_count, Stats};
+
+pub const AGGREGATIONS: &[&str] = &["mean", "median", "trimmed-mean", "weighted-mean", "mad"];
+
+/// Share of the lowest and of the highest rates left out of a trimmed mean
+const TRIM_FRACTION: Decimal = Decimal::from_parts(2, 0, 0, false, 1);
+/// Robust z-score beyond which a rate is an outlier, as suggested by Iglewicz and Hoaglin
+const OUTLIER_THRESHOLD: Decimal = Decimal::from_parts(35, 0, 0, false, 1);
+/// Scales the median absolute deviation to the standard deviation of normal data
+const MAD_SCALE: Decimal = Decimal::from_parts(14826, 0, 0, false, 4);
+/// Scales the mean absolute deviation likewise, used if more than half of the rates agree
+const MEAN_AD_SCALE: Decimal = Decimal::from_parts(12533, 0, 0, false, 4);
+
+/// How the rates of several routes are combined into one.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Aggregation {
+    #[default]
+    Mean,
+    Median,
+    /// Mean without the lowest and highest 20% of the rates
+    TrimmedMean,
+    /// Mean with routes weighted by the configured weights of their providers
+    WeightedMean,
+    /// Mean of the rates left after rejecting outliers by their median absolute
+    /// deviation
+    #[serde(rename = "mad")]
+    RejectOutliers,
+}
+
+impl FromStr for Aggregation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "mean" => Ok(Aggregation::Mean),
+            "median" => Ok(Aggregation::Median),
+            "trimmed-mean" => Ok(Aggregation::TrimmedMean),
+            "weighted-mean" => Ok(Aggregation::WeightedMean),
+            "mad" => Ok(Aggregation::RejectOutliers),
+            _ => Err(anyhow!(
+                "Unknown aggregation {}, expected one of {}",
+                s,
+                AGGREGATIONS.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Aggregation::Mean => "mean",
+            Aggregation::Median => "median",
+            Aggregation::TrimmedMean => "trimmed mean",
+            Aggregation::WeightedMean => "weighted mean",
+            Aggregation::RejectOutliers => "mean without outliers",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Combined rate along with the rates that did not count, by their position and the
+/// reason they were left out.
+#[derive(Debug, PartialEq)]
+pub struct Aggregate {
+    pub rate: Decimal,
+    pub discarded: HashMap<usize, String>,
+}
+
+/// An aggregation along with the provider weights it may need and the safeguards its
+/// result has to pass.
+#[derive(Debug, Clone, Default)]
+pub struct Aggregator {
+    pub aggregation: Aggregation,
+    /// Weights of providers by name, 1 unless configured
+    pub weights: HashMap<String, Decimal>,
+    /// Least number of independent sources a rate must be based on
+    pub min_providers: Option<usize>,
+    /// Largest accepted relative spread of the rates in basis points
+    pub max_spread_bps: Option<Decimal>,
+}
+
+/// Parses a relative spread like `0.5%` or `50bps` into basis points. Plain numbers are
+/// percentages.
+pub fn parse_spread(input: &str) -> Result<Decimal, anyhow::Error> {
+    let input = input.trim();
+    let (number, factor) = match (input.strip_suffix("bps"), input.strip_suffix('%')) {
+        (Some(bps), _) => (bps, Decimal::new(1, 0)),
+        (_, Some(percent)) => (percent, Decimal::new(100, 0)),
+        _ => (input, Decimal::new(100, 0)),
+    };
+
+    Decimal::from_str(number.trim())
+        .ok()
+        .filter(|spread| !spread.is_sign_negative())
+        .map(|spread| spread * factor)
+        .with_context(|| {
+            format!(
+                "Invalid spread '{}', expected e.g. '0.5%' or '50bps'",
+                input
+            )
+        })
+}
+
+impl Aggregator {
+    /// The default aggregation with the provider weights of `config`.
+    pub fn from_config(config: &Config) -> Result<Self, anyhow::Error> {
+        let weights = config
+            .weights
+            .iter()
+            .map(|(provider, weight)| {
+                Decimal::from_f64(*weight)
+                    .filter(|w| !w.is_sign_negative())
+                    .map(|w| (provider.clone(), w))
+                    .with_context(|| format!("Invalid weight {} of {}", weight, provider))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Aggregator {
+            weights,
+            ..Default::default()
+        })
+    }
+
+    /// Fails unless the rates that counted towards an aggregate come from enough
+    /// independent sources and agree closely enough. Routes through the same providers
+    /// count as a single source. Without an aggregate, no rate counted.
+    pub fn check<E>(
+        &self,
+        routes: &[Route],
+        results: &[Result<Decimal, E>],
+        aggregate: Option<&Aggregate>,
+        providers: &[Box<dyn Provider>],
+    ) -> Result<(), anyhow::Error> {
+        let aggregate = match aggregate {
+            Some(aggregate) => aggregate,
+            None => return self.check_rates(0, &[]),
+        };
+        let counted = routes
+            .iter()
+            .zip(results)
+            .enumerate()
+            .filter(|(i, _)| !aggregate.discarded.contains_key(i))
+            .filter_map(|(_, (route, result))| result.as_ref().ok().map(|rate| (route, *rate)))
+            .collect::<Vec<_>>();
+
+        let sources = counted
+            .iter()
+            .map(|(route, _)| {
+                let mut names = route
+                    .legs
+                    .iter()
+                    .map(|l| providers[l.provider].get_name())
+                    .collect::<Vec<_>>();
+                names.sort();
+                names.dedup();
+                names
+            })
+            .collect::<HashSet<_>>();
+        let rates = counted.iter().map(|(_, rate)| *rate).collect::<Vec<_>>();
+
+        self.check_rates(sources.len(), &rates)
+    }
+
+    /// Checks rates gathered from a number of independent sources against the limits.
+    fn check_rates(&self, sources: usize, rates: &[Decimal]) -> Result<(), anyhow::Error> {
+        if let Some(min_providers) = self.min_providers {
+            if sources < min_providers {
+                return Err(anyhow!(
+                    "Rate is based on {} independent source(s), but at least {} are required",
+                    sources,
+                    min_providers
+                ));
+            }
+        }
+
+        if let Some(max_spread_bps) = self.max_spread_bps {
+            match rates.spread_bps() {
+                Some(spread) if spread > max_spread_bps => {
+                    return Err(anyhow!(
+                        "Rates {} to {} spread by {} bps, more than the accepted {} bps",
+                        rates.minimum().unwrap_or_default(),
+                        rates.maximum().unwrap_or_default(),
+                        spread.round_dp(2),
+                        max_spread_bps.normalize()
+                    ))
+                }
+                Some(_) => {}
+                None => return Err(anyhow!("Cannot compute the spread of the rates")),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Weight of a route, the product of the weights of its providers.
+    pub fn weight(&self, route: &Route, providers: &[Box<dyn Provider>]) -> Decimal {
+        route
+            .legs
+            .iter()
+            .map(|l| {
+                let name = providers[l.provider].get_name();
+                self.weights
+                    .get(&name)
+                    .cloned()
+                    .unwrap_or(Decimal::new(1, 0))
+            })
+            .fold(Decimal::new(1, 0), |product, weight| product * weight)
+    }
+
+    /// Combines the rates of routes, of which failed ones are skipped.
+    pub fn aggregate_routes<E>(
+        &self,
+        routes: &[Route],
+        results: &[Result<Decimal, E>],
+        providers: &[Box<dyn Provider>],
+    ) -> Option<Aggregate> {
+        let (positions, rates, weights) = routes.iter().zip(results).enumerate().fold(
+            (vec![], vec![], vec![]),
+            |(mut positions, mut rates, mut weights), (i, (route, result))| {
+                if let Ok(rate) = result {
+                    positions.push(i);
+                    rates.push(*rate);
+                    weights.push(self.weight(route, providers));
+                }
+                (positions, rates, weights)
+            },
+        );
+
+        let aggregate = self.aggregation.aggregate(&rates, &weights)?;
+        Some(Aggregate {
+            rate: aggregate.rate,
+            discarded: aggregate
+                .discarded
+                .into_iter()
+                .map(|(i, reason)| (positions[i], reason))
+                .collect(),
+        })
+    }
+}
+
+impl Aggregation {
+    /// Combines rates into one. `weights` are only used by the weighted mean.
+    pub fn aggregate(self, rates: &[Decimal], weights: &[Decimal]) -> Option<Aggregate> {
+        let mut discarded = HashMap::new();
+
+        let rate = match self {
+            Aggregation::Mean => rates.mean()?,
+            Aggregation::Median => rates.median()?,
+            Aggregation::WeightedMean => rates.weighted_mean(weights)?,
+            Aggregation::TrimmedMean => {
+                let mut order = (0..rates.len()).collect::<Vec<_>>();
+                order.sort_by_key(|i| rates[*i]);
+                let trimmed = trim_count(rates.len(), TRIM_FRACTION);
+                for i in order.iter().take(trimmed) {
+                    discarded.insert(*i, String::from("among the lowest rates"));
+                }
+                for i in order.iter().rev().take(trimmed) {
+                    discarded.insert(*i, String::from("among the highest rates"));
+                }
+                rates.trimmed_mean(TRIM_FRACTION)?
+            }
+            Aggregation::RejectOutliers => {
+                let median = rates.median()?;
+                let deviations = rates
+                    .iter()
+                    .map(|r| (*r - median).abs())
+                    .collect::<Vec<_>>();
+                let scale = match rates.median_absolute_deviation()? {
+                    mad if !mad.is_zero() => mad * MAD_SCALE,
+                    _ => deviations.mean()? * MEAN_AD_SCALE,
+                };
+
+                if !scale.is_zero() {
+                    for (i, deviation) in deviations.iter().enumerate() {
+                        let score = deviation / scale;
+                        if score > OUTLIER_THRESHOLD {
+                            let reason = format!(
+                                "outlier, {} deviations from the median {}",
+                                score.round_dp(1),
+                                median
+                            );
+                            discarded.insert(i, reason);
+                        }
+                    }
+                }
+
+                rates
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !discarded.contains_key(i))
+                    .map(|(_, r)| r)
+                    .mean()?
+            }
+        };
+
+        Some(Aggregate { rate, discarded })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_spread, Aggregation, Aggregator, TRIM_FRACTION};
+
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    fn rates(values: &[i64]) -> Vec<Decimal> {
+        values.iter().map(|v| Decimal::new(*v, 2)).collect()
+    }
+
+    #[test]
+    fn parses_aggregations() {
+        assert_eq!(
+            Aggregation::from_str("mad").unwrap(),
+            Aggregation::RejectOutliers
+        );
+        assert!(Aggregation::from_str("mode").is_err());
+        assert_eq!(TRIM_FRACTION, Decimal::new(2, 1));
+    }
+
+    #[test]
+    fn median_ignores_broken_rate() {
+        let rates = rates(&[117, 118, 11700]);
+
+        let aggregate = Aggregation::Median.aggregate(&rates, &[]).unwrap();
+
+        assert_eq!(aggregate.rate, Decimal::new(118, 2));
+    }
+
+    #[test]
+    fn rejects_outliers_by_median_absolute_deviation() {
+        let rates = rates(&[117, 118, 116, 117, 250]);
+
+        let aggregate = Aggregation::RejectOutliers.aggregate(&rates, &[]).unwrap();
+
+        assert_eq!(aggregate.rate, Decimal::new(117, 2));
+        assert_eq!(aggregate.discarded.keys().collect::<Vec<_>>(), vec![&4]);
+    }
+
+    #[test]
+    fn keeps_agreeing_rates() {
+        let rates = rates(&[117, 117, 117, 118]);
+
+        let aggregate = Aggregation::RejectOutliers.aggregate(&rates, &[]).unwrap();
+
+        assert!(aggregate.discarded.is_empty());
+    }
+
+    #[test]
+    fn trims_lowest_and_highest_rates() {
+        let rates = rates(&[100, 117, 118, 117, 200]);
+
+        let aggregate = Aggregation::TrimmedMean.aggregate(&rates, &[]).unwrap();
+
+        assert_eq!(aggregate.rate.round_dp(4), Decimal::new(11733, 4));
+        assert_eq!(aggregate.discarded.len(), 2);
+        assert!(aggregate.discarded[&0].contains("lowest"));
+        assert!(aggregate.discarded[&4].contains("highest"));
+    }
+
+    #[test]
+    fn parses_spreads_into_basis_points() {
+        assert_eq!(parse_spread("0.5%").unwrap(), Decimal::new(50, 0));
+        assert_eq!(parse_spread("50bps").unwrap(), Decimal::new(50, 0));
+        assert_eq!(parse_spread("2").unwrap(), Decimal::new(200, 0));
+        assert!(parse_spread("-1%").is_err());
+        assert!(parse_spread("wide").is_err());
+    }
+
+    #[test]
+    fn requires_enough_sources() {
+        let aggregator = Aggregator {
+            min_providers: Some(2),
+            ..Aggregator::default()
+        };
+
+        assert!(aggregator.check_rates(1, &rates(&[117])).is_err());
+        assert!(aggregator.check_rates(2, &rates(&[117, 118])).is_ok());
+    }
+
+    #[test]
+    fn rejects_disagreeing_rates() {
+        let aggregator = Aggregator {
+            max_spread_bps: Some(parse_spread("0.5%").unwrap()),
+            ..Aggregator::default()
+        };
+
+        assert!(aggregator.check_rates(2, &rates(&[1000, 1004])).is_ok());
+        let error = aggregator.check_rates(2, &rates(&[100, 102])).unwrap_err();
+        assert!(error.to_string().contains("more than the accepted 50 bps"));
+    }
+
+    #[test]
+    fn weighs_rates() {
+        let rates = rates(&[100, 200]);
+        let weights = vec![Decimal::new(3, 0), Decimal::new(1, 0)];
+
+        let aggregate = Aggregation::WeightedMean
+            .aggregate(&rates, &weights)
+            .unwrap();
+
+        assert_eq!(aggregate.rate, Decimal::new(125, 2));
+    }
+}
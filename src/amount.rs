@@ -0,0 +1,230 @@
+use anyhow::anyhow;
+use rust_decimal::Decimal;
+
+use std::str::FromStr;
+
+use crate::currency::{self, Symbol};
+
+/// Characters that only ever group digits, like in `1_000` or `1'000`.
+const GROUPING: &[char] = &['_', '\''];
+/// Characters that group digits or separate decimals, depending on the locale.
+const SEPARATORS: &[char] = &[',', '.'];
+/// Longest first, so that `bn` is not taken for `b` followed by `n`
+const MAGNITUDES: &[(&str, i64)] = &[
+    ("bn", 1_000_000_000),
+    ("k", 1_000),
+    ("m", 1_000_000),
+    ("b", 1_000_000_000),
+];
+
+/// An amount like `100`, `$100`, `100€`, `1.5k` or `1.234,56`, along with the currency
+/// of a sign or code written next to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Amount {
+    pub value: Decimal,
+    pub currency: Option<Symbol>,
+}
+
+impl FromStr for Amount {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, anyhow::Error> {
+        let (negative, rest) = match input.chars().next() {
+            Some('-') => (true, &input[1..]),
+            Some('+') => (false, &input[1..]),
+            _ => (false, input),
+        };
+
+        let start = rest
+            .find(|c: char| c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("Invalid amount '{}'", input))?;
+        let end = rest[start..]
+            .find(|c: char| {
+                !c.is_ascii_digit() && !SEPARATORS.contains(&c) && !GROUPING.contains(&c)
+            })
+            .map(|i| start + i)
+            .unwrap_or_else(|| rest.len());
+        let (prefix, number, suffix) = (&rest[..start], &rest[start..end], &rest[end..]);
+
+        let value = parse_number(number).ok_or_else(|| anyhow!("Invalid amount '{}'", input))?;
+        let (magnitude, suffix_currency) = parse_suffix(suffix)
+            .ok_or_else(|| anyhow!("Unknown currency or suffix '{}' in '{}'", suffix, input))?;
+        let prefix_currency = match prefix.is_empty() {
+            true => None,
+            _ => Some(
+                currency::find_exact(prefix)
+                    .map(|c| c.symbol)
+                    .ok_or_else(|| anyhow!("Unknown currency '{}' in '{}'", prefix, input))?,
+            ),
+        };
+
+        let currency = match (prefix_currency, suffix_currency) {
+            (Some(_), Some(_)) => return Err(anyhow!("Two currencies given in '{}'", input)),
+            (prefix, suffix) => prefix.or(suffix),
+        };
+        let value = match magnitude {
+            1 => value,
+            _ => value
+                .checked_mul(Decimal::new(magnitude, 0))
+                .ok_or_else(|| anyhow!("Amount '{}' is too large", input))?
+                .normalize(),
+        };
+
+        Ok(Amount {
+            value: if negative { -value } else { value },
+            currency,
+        })
+    }
+}
+
+/// Reads digits with optional thousands and decimal separators in either locale, e.g.
+/// `1,234.56` and `1.234,56`. If only one separator is used, it separates decimals
+/// unless it occurs several times or is a comma followed by exactly three digits, as in
+/// `1,234`.
+fn parse_number(input: &str) -> Option<Decimal> {
+    let number = input
+        .chars()
+        .filter(|c| !GROUPING.contains(c))
+        .collect::<String>();
+
+    let decimal = match (number.rfind(','), number.rfind('.')) {
+        (Some(comma), Some(dot)) => Some(comma.max(dot)),
+        (Some(i), None) | (None, Some(i)) => {
+            let separator = &number[i..=i];
+            let is_grouping = number.matches(separator).count() > 1
+                || (separator == "," && number.len() - i - 1 == 3);
+            if is_grouping {
+                None
+            } else {
+                Some(i)
+            }
+        }
+        (None, None) => None,
+    };
+    let (integer, fraction) = match decimal {
+        Some(i) => (&number[..i], &number[i + 1..]),
+        None => (&number[..], ""),
+    };
+
+    // Groups of an integer part must all be separated alike, e.g. not `1.234,567.8`
+    if let Some(i) = decimal {
+        if integer.contains(&number[i..=i]) {
+            return None;
+        }
+    }
+    let groups = integer.split(SEPARATORS).collect::<Vec<_>>();
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    let valid_groups = match groups.as_slice() {
+        [single] => is_digits(single),
+        [first, rest @ ..] => {
+            (1..=3).contains(&first.len())
+                && is_digits(first)
+                && rest.iter().all(|g| g.len() == 3 && is_digits(g))
+        }
+        [] => false,
+    };
+    if !valid_groups || !is_digits(fraction) || (decimal.is_some() && fraction.is_empty()) {
+        return None;
+    }
+
+    let digits = match fraction.is_empty() {
+        true => groups.concat(),
+        _ => format!("{}.{}", groups.concat(), fraction),
+    };
+    Decimal::from_str(&digits).ok()
+}
+
+/// Splits a suffix like `k`, `€` or `k€` into a magnitude and a currency.
+fn parse_suffix(suffix: &str) -> Option<(i64, Option<Symbol>)> {
+    if suffix.is_empty() {
+        return Some((1, None));
+    }
+
+    let lowercase = suffix.to_lowercase();
+    if let Some((_, magnitude)) = MAGNITUDES.iter().find(|(s, _)| *s == lowercase) {
+        return Some((*magnitude, None));
+    }
+    if let Some(currency) = currency::find_exact(suffix) {
+        return Some((1, Some(currency.symbol)));
+    }
+
+    MAGNITUDES.iter().find_map(|(s, magnitude)| {
+        lowercase
+            .strip_prefix(s)
+            .and_then(currency::find_exact)
+            .map(|c| (*magnitude, Some(c.symbol)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Amount;
+    use crate::currency::Symbol;
+
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    fn amount(input: &str) -> (Decimal, Option<String>) {
+        let amount = Amount::from_str(input).unwrap();
+        (amount.value, amount.currency.map(|s| s.to_string()))
+    }
+
+    fn symbol(code: &str) -> Option<String> {
+        Symbol::from_str(code).ok().map(|s| s.to_string())
+    }
+
+    #[test]
+    fn parses_plain_numbers() {
+        assert_eq!(amount("100"), (Decimal::new(100, 0), None));
+        assert_eq!(amount("12.5"), (Decimal::new(125, 1), None));
+        assert_eq!(amount("-3"), (Decimal::new(-3, 0), None));
+    }
+
+    #[test]
+    fn parses_locale_separators() {
+        let expected = Decimal::new(123456, 2);
+
+        for input in &["1,234.56", "1.234,56", "1234,56", "1'234.56", "1_234.56"] {
+            assert_eq!(amount(input).0, expected, "{}", input);
+        }
+        assert_eq!(amount("1,234").0, Decimal::new(1234, 0));
+        assert_eq!(amount("1.234").0, Decimal::new(1234, 3));
+        assert_eq!(amount("1.234.567").0, Decimal::new(1234567, 0));
+    }
+
+    #[test]
+    fn parses_magnitude_suffixes() {
+        assert_eq!(amount("1.5k").0, Decimal::new(1500, 0));
+        assert_eq!(amount("2m").0, Decimal::new(2_000_000, 0));
+        assert_eq!(amount("3bn").0, Decimal::new(3_000_000_000, 0));
+        assert_eq!(amount("1.5K").0, Decimal::new(1500, 0));
+    }
+
+    #[test]
+    fn parses_currency_signs() {
+        assert_eq!(amount("$100"), (Decimal::new(100, 0), symbol("USD")));
+        assert_eq!(amount("100€"), (Decimal::new(100, 0), symbol("EUR")));
+        assert_eq!(amount("1.5k€"), (Decimal::new(1500, 0), symbol("EUR")));
+        assert_eq!(amount("-£2,50"), (Decimal::new(-250, 2), symbol("GBP")));
+        assert_eq!(amount("100chf"), (Decimal::new(100, 0), symbol("CHF")));
+    }
+
+    #[test]
+    fn fails_on_invalid_amounts() {
+        for input in &[
+            "abc",
+            "1,23,4",
+            "1.234,567.8",
+            "12.",
+            "$100€",
+            "100xyz",
+            "1..2",
+        ] {
+            assert!(
+                Amount::from_str(input).is_err(),
+                "{} should not parse",
+                input
+            );
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Context};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use std::str::FromStr;
+
+use crate::cli::{parse_currencies, parse_date, split_date, SEPERATORS};
+use currency_converter::amount::Amount;
+use currency_converter::currency::{Currency, Symbol, SymbolPair};
+
+/// Pair and date of a conversion. Every key is fetched once per batch.
+pub type Key = (SymbolPair, Option<NaiveDate>);
+
+/// A single conversion of a batch.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub amount: Decimal,
+    pub pair: SymbolPair,
+    pub date: Option<NaiveDate>,
+}
+
+impl Entry {
+    pub fn key(&self) -> Key {
+        (self.pair, self.date)
+    }
+}
+
+/// Parses the lines of a batch along with their line numbers. Blank lines, comments
+/// starting with `#` and a CSV header are skipped.
+pub fn parse_lines(
+    input: &str,
+    quote: Option<Symbol>,
+) -> Vec<(usize, Result<Entry, anyhow::Error>)> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .filter(|(i, line)| !(*i == 1 && is_header(line)))
+        .map(|(i, line)| (i, parse_line(line, quote)))
+        .collect()
+}
+
+/// Parses a line like `12.50 eur in usd on 2020-03-15` or a CSV row like
+/// `12.50,eur,2020-03-15`. CSV rows have no target currency and are converted into
+/// `quote`.
+pub fn parse_line(line: &str, quote: Option<Symbol>) -> Result<Entry, anyhow::Error> {
+    let mut words = line.split_whitespace().collect::<Vec<_>>();
+    // Commas of text lines group digits, like in `1,234.50 eur in usd`
+    if line.contains(',') && !words.iter().any(|w| SEPERATORS.contains(w)) {
+        return parse_row(line, quote);
+    }
+
+    if words.is_empty() {
+        return Err(anyhow!("Empty line"));
+    }
+    let amount = Amount::from_str(words.remove(0))?;
+    let (words, date) = split_date(words)?;
+    let pair =
+        parse_currencies(words, amount.currency).context("Failed to parse currency string")?;
+
+    Ok(Entry {
+        amount: amount.value,
+        pair,
+        date,
+    })
+}
+
+fn parse_row(line: &str, quote: Option<Symbol>) -> Result<Entry, anyhow::Error> {
+    let record = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(line.as_bytes())
+        .records()
+        .next()
+        .context("Empty row")?
+        .context("Failed to parse CSV row")?;
+    let quote = quote.context("CSV rows need a target currency, see --into")?;
+
+    let amount = Amount::from_str(record.get(0).unwrap_or_default())?;
+    let base = match (amount.currency, record.get(1).filter(|c| !c.is_empty())) {
+        (Some(base), None) => base,
+        (_, name) => Currency::from_str(name.unwrap_or_default())?.symbol,
+    };
+    let date = record
+        .get(2)
+        .filter(|d| !d.is_empty())
+        .map(parse_date)
+        .transpose()?;
+
+    Ok(Entry {
+        amount: amount.value,
+        pair: SymbolPair { base, quote },
+        date,
+    })
+}
+
+fn is_header(line: &str) -> bool {
+    line.contains(',')
+        && line
+            .split(',')
+            .next()
+            .map(|field| Amount::from_str(field.trim().trim_matches('"')).is_err())
+            .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_line, parse_lines, Entry};
+    use currency_converter::currency::{Symbol, SymbolPair};
+
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    fn pair(base: &str, quote: &str) -> SymbolPair {
+        SymbolPair {
+            base: Symbol::from_str(base).unwrap(),
+            quote: Symbol::from_str(quote).unwrap(),
+        }
+    }
+
+    #[test]
+    fn parses_text_line() {
+        let entry = parse_line("12.50 eur in usd on 2020-03-15", None).unwrap();
+
+        assert_eq!(
+            entry,
+            Entry {
+                amount: Decimal::new(1250, 2),
+                pair: pair("EUR", "USD"),
+                date: NaiveDate::from_ymd_opt(2020, 3, 15),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_text_line_with_grouped_digits() {
+        let entry = parse_line("1,234.50 eur in usd", None).unwrap();
+
+        assert_eq!(entry.amount, Decimal::new(123450, 2));
+        assert_eq!(entry.pair, pair("EUR", "USD"));
+    }
+
+    #[test]
+    fn parses_csv_row_into_quote() {
+        let quote = Symbol::from_str("USD").ok();
+
+        let entry = parse_line("12.50, turkish lira", quote).unwrap();
+
+        assert_eq!(entry.pair, pair("TRY", "USD"));
+        assert_eq!(entry.date, None);
+    }
+
+    #[test]
+    fn fails_csv_row_without_quote() {
+        let entry = parse_line("12.50,eur,2020-03-15", None);
+
+        assert!(entry.is_err());
+    }
+
+    #[test]
+    fn skips_header_comments_and_blank_lines() {
+        let input = "amount,currency,date\n# travel\n\n12.50,eur,2020-03-15\nfoo,eur\n";
+
+        let entries = parse_lines(input, Symbol::from_str("USD").ok());
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, 4);
+        assert!(entries[0].1.is_ok());
+        assert_eq!(entries[1].0, 5);
+        assert!(entries[1].1.is_err(), "Invalid amounts should be reported");
+    }
+}
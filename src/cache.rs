@@ -0,0 +1,919 @@
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use reqwest::Url;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::currency::Symbol;
+use crate::providers::error::ProviderError;
+use crate::providers::provider::{FetchedRates, Provider, Rates, Series, Usage};
+
+const CACHE_DIR: &str = "currency-converter";
+const CACHE_FILE: &str = "rates.json";
+/// Latest rates older than this are not saved. Online they are fetched again anyway,
+/// and offline they are too old to be trusted.
+const MAX_LATEST_AGE: Duration = Duration::from_secs(30 * 86400);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CachedRate {
+    pub provider: String,
+    pub base: Symbol,
+    pub quote: Symbol,
+    pub rate: Decimal,
+    /// Date of historical rates, `None` for the latest rates
+    #[serde(default)]
+    pub date: Option<NaiveDate>,
+    /// Seconds since the UNIX epoch
+    pub fetched_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CacheFile {
+    rates: Vec<CachedRate>,
+}
+
+type CacheKey = (String, Symbol, Symbol, Option<NaiveDate>);
+
+/// Rates fetched by previous invocations, persisted as JSON in the user's cache dir.
+#[derive(Default)]
+pub struct RateCache {
+    path: Option<PathBuf>,
+    rates: HashMap<CacheKey, CachedRate>,
+    // Rates served from the cache during the current conversion
+    hits: HashSet<CacheKey>,
+    dirty: bool,
+}
+
+impl CachedRate {
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.fetched_at))
+    }
+
+    fn key(&self) -> CacheKey {
+        (self.provider.clone(), self.base, self.quote, self.date)
+    }
+}
+
+impl RateCache {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|d| d.join(CACHE_DIR).join(CACHE_FILE))
+    }
+
+    /// Loads the cache from `path`. A missing or unreadable cache file results in an
+    /// empty cache as it will be rewritten anyway.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let rates = path
+            .as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|content| serde_json::from_str::<CacheFile>(&content).ok())
+            .unwrap_or_default()
+            .rates
+            .into_iter()
+            .map(|r| (r.key(), r))
+            .collect();
+
+        RateCache {
+            path,
+            rates,
+            ..Default::default()
+        }
+    }
+
+    /// Writes the cache to its file if rates were added or removed. Latest rates older
+    /// than a month are left out.
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        let path = match &self.path {
+            Some(path) if self.dirty => path,
+            _ => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create cache dir {}", dir.display()))?;
+        }
+        let mut rates = self
+            .rates
+            .values()
+            .filter(|r| r.date.is_some() || r.age() < MAX_LATEST_AGE)
+            .cloned()
+            .collect::<Vec<_>>();
+        rates.sort_by_key(|r| r.key());
+        let content = serde_json::to_string_pretty(&CacheFile { rates })?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write cache file {}", path.display()))
+    }
+
+    /// Returns the cached rate if it is younger than `max_age`. Historical rates do not
+    /// change and are returned regardless of their age, unless `max_age` is zero.
+    pub fn get(
+        &self,
+        provider: &str,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+        max_age: Duration,
+    ) -> Option<&CachedRate> {
+        let key = (String::from(provider), base, quote, date);
+
+        self.rates.get(&key).filter(|r| {
+            let is_final = r.date.is_some() && max_age > Duration::from_secs(0);
+            is_final || r.age() < max_age
+        })
+    }
+
+    pub fn insert(
+        &mut self,
+        provider: &str,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+        rate: Decimal,
+    ) {
+        let entry = CachedRate {
+            provider: String::from(provider),
+            base,
+            quote,
+            rate,
+            date,
+            fetched_at: now(),
+        };
+
+        self.rates.insert(entry.key(), entry);
+        self.dirty = true;
+    }
+
+    /// Forgets which rates were served, before starting another conversion.
+    pub fn clear_hits(&mut self) {
+        self.hits.clear();
+    }
+
+    /// Drops all latest rates so that they are fetched again. Historical rates are kept
+    /// as they do not change.
+    pub fn expire_latest(&mut self) {
+        self.rates.retain(|_, r| r.date.is_some());
+        self.dirty = true;
+    }
+
+    /// Number of rates cached for a provider
+    pub fn count(&self, provider: &str) -> usize {
+        self.rates
+            .values()
+            .filter(|r| r.provider == provider)
+            .count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    /// Returns the age of the rate if it was served from the cache during this run.
+    pub fn served(
+        &self,
+        provider: &str,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+    ) -> Option<Duration> {
+        let key = (String::from(provider), base, quote, date);
+
+        if self.hits.contains(&key) {
+            self.rates.get(&key).map(|r| r.age())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the most recently fetched rate of any provider for the pair, derived
+    /// from the inverse pair if that is more recent, together with its age.
+    pub fn latest(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+    ) -> Option<(Decimal, Duration)> {
+        self.rates
+            .values()
+            .filter(|r| r.date == date)
+            .filter_map(|r| {
+                if r.base == base && r.quote == quote {
+                    Some((r.rate, r))
+                } else if r.base == quote && r.quote == base && !r.rate.is_zero() {
+                    Some((Decimal::new(1, 0) / r.rate, r))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|(_, r)| r.fetched_at)
+            .map(|(rate, r)| (rate, r.age()))
+    }
+
+    fn hit(
+        &mut self,
+        provider: &str,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+        max_age: Duration,
+    ) -> Option<Decimal> {
+        let rate = self.get(provider, base, quote, date, max_age)?.rate;
+        self.hits
+            .insert((String::from(provider), base, quote, date));
+        Some(rate)
+    }
+}
+
+/// Serves rates from the cache if they are younger than `max_age` and caches all
+/// rates fetched from the wrapped provider.
+pub struct CachedProvider {
+    inner: Box<dyn Provider>,
+    cache: Arc<Mutex<RateCache>>,
+    max_age: Duration,
+}
+
+impl CachedProvider {
+    pub fn new(inner: Box<dyn Provider>, cache: Arc<Mutex<RateCache>>, max_age: Duration) -> Self {
+        Self {
+            inner,
+            cache,
+            max_age,
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for CachedProvider {
+    fn get_name(&self) -> String {
+        self.inner.get_name()
+    }
+    fn supports(&self, symbol: &Symbol) -> bool {
+        self.inner.supports(symbol)
+    }
+    fn supports_date(&self, date: &NaiveDate) -> Result<(), ProviderError> {
+        self.inner.supports_date(date)
+    }
+    fn usage(&self) -> Option<Usage> {
+        self.inner.usage()
+    }
+    fn build_url(
+        &self,
+        base: &Symbol,
+        quote: &Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Url, ProviderError> {
+        self.inner.build_url(base, quote, date)
+    }
+    fn parse_rate_from_response(
+        &self,
+        quote: &Symbol,
+        response: &str,
+    ) -> Result<Decimal, ProviderError> {
+        self.inner.parse_rate_from_response(quote, response)
+    }
+    async fn get_rate(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Decimal, ProviderError> {
+        let name = self.get_name();
+
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .hit(&name, base, quote, date, self.max_age);
+        if let Some(rate) = cached {
+            return Ok(rate);
+        }
+
+        let rate = self.inner.get_rate(base, quote, date).await?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(&name, base, quote, date, rate);
+
+        Ok(rate)
+    }
+    async fn get_rates(
+        &self,
+        base: Symbol,
+        quotes: &[Symbol],
+        date: Option<NaiveDate>,
+    ) -> Result<FetchedRates, ProviderError> {
+        let name = self.get_name();
+
+        let mut rates = Rates::new();
+        {
+            let mut cache = self.cache.lock().unwrap();
+            for quote in quotes.iter() {
+                if let Some(rate) = cache.hit(&name, base, *quote, date, self.max_age) {
+                    rates.insert(*quote, rate);
+                }
+            }
+        }
+
+        let missing = quotes
+            .iter()
+            .filter(|q| !rates.contains_key(q))
+            .cloned()
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(rates.into());
+        }
+
+        match self.inner.get_rates(base, &missing, date).await {
+            Ok(mut fetched) => {
+                let mut cache = self.cache.lock().unwrap();
+                for (quote, rate) in fetched.rates.iter() {
+                    cache.insert(&name, base, *quote, date, *rate);
+                }
+                fetched.rates.extend(rates);
+                Ok(fetched)
+            }
+            Err(e) if rates.is_empty() => Err(e),
+            Err(e) => Ok(FetchedRates {
+                rates,
+                error: Some(e),
+            }),
+        }
+    }
+    async fn get_series(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Series, ProviderError> {
+        let name = self.get_name();
+        let series = self.inner.get_series(base, quote, from, to).await?;
+
+        // Days of a series are remembered like single historical rates
+        let mut cache = self.cache.lock().unwrap();
+        for (date, rate) in series.iter() {
+            cache.insert(&name, base, quote, Some(*date), *rate);
+        }
+
+        Ok(series)
+    }
+}
+
+/// Rates fetched or served during an interactive session, reused regardless of their
+/// age until cleared. Unlike the `RateCache`, they are never saved.
+#[derive(Default)]
+pub struct SessionRates {
+    rates: HashMap<CacheKey, Decimal>,
+}
+
+impl SessionRates {
+    /// Forgets all rates, so that they are looked up again.
+    pub fn clear(&mut self) {
+        self.rates.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.rates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+}
+
+/// Serves rates of the session before asking the wrapped provider and keeps all rates
+/// it returns for the session. Rates served again are marked as served from `cache`,
+/// so that they are labelled with their age.
+pub struct SessionProvider {
+    inner: Box<dyn Provider>,
+    session: Arc<Mutex<SessionRates>>,
+    cache: Option<Arc<Mutex<RateCache>>>,
+}
+
+impl SessionProvider {
+    pub fn new(
+        inner: Box<dyn Provider>,
+        session: Arc<Mutex<SessionRates>>,
+        cache: Option<Arc<Mutex<RateCache>>>,
+    ) -> Self {
+        Self {
+            inner,
+            session,
+            cache,
+        }
+    }
+
+    fn reuse(&self, base: Symbol, quotes: &[Symbol], date: Option<NaiveDate>) -> Rates {
+        let name = self.get_name();
+        let session = self.session.lock().unwrap();
+        let mut cache = self.cache.as_ref().map(|c| c.lock().unwrap());
+
+        let mut rates = Rates::new();
+        for quote in quotes {
+            let key = (name.clone(), base, *quote, date);
+            if let Some(rate) = session.rates.get(&key) {
+                if let Some(cache) = cache.as_mut() {
+                    cache.hits.insert(key);
+                }
+                rates.insert(*quote, *rate);
+            }
+        }
+        rates
+    }
+
+    fn keep(&self, base: Symbol, rates: &Rates, date: Option<NaiveDate>) {
+        let name = self.get_name();
+        let mut session = self.session.lock().unwrap();
+        for (quote, rate) in rates.iter() {
+            session
+                .rates
+                .insert((name.clone(), base, *quote, date), *rate);
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for SessionProvider {
+    fn get_name(&self) -> String {
+        self.inner.get_name()
+    }
+    fn supports(&self, symbol: &Symbol) -> bool {
+        self.inner.supports(symbol)
+    }
+    fn supports_date(&self, date: &NaiveDate) -> Result<(), ProviderError> {
+        self.inner.supports_date(date)
+    }
+    fn usage(&self) -> Option<Usage> {
+        self.inner.usage()
+    }
+    fn build_url(
+        &self,
+        base: &Symbol,
+        quote: &Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Url, ProviderError> {
+        self.inner.build_url(base, quote, date)
+    }
+    fn parse_rate_from_response(
+        &self,
+        quote: &Symbol,
+        response: &str,
+    ) -> Result<Decimal, ProviderError> {
+        self.inner.parse_rate_from_response(quote, response)
+    }
+    async fn get_rate(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Decimal, ProviderError> {
+        if let Some(rate) = self.reuse(base, &[quote], date).remove(&quote) {
+            return Ok(rate);
+        }
+
+        let rate = self.inner.get_rate(base, quote, date).await?;
+        self.keep(base, &[(quote, rate)].iter().cloned().collect(), date);
+
+        Ok(rate)
+    }
+    async fn get_rates(
+        &self,
+        base: Symbol,
+        quotes: &[Symbol],
+        date: Option<NaiveDate>,
+    ) -> Result<FetchedRates, ProviderError> {
+        let rates = self.reuse(base, quotes, date);
+
+        let missing = quotes
+            .iter()
+            .filter(|q| !rates.contains_key(q))
+            .cloned()
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(rates.into());
+        }
+
+        match self.inner.get_rates(base, &missing, date).await {
+            Ok(mut fetched) => {
+                self.keep(base, &fetched.rates, date);
+                fetched.rates.extend(rates);
+                Ok(fetched)
+            }
+            Err(e) if rates.is_empty() => Err(e),
+            Err(e) => Ok(FetchedRates {
+                rates,
+                error: Some(e),
+            }),
+        }
+    }
+    async fn get_series(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Series, ProviderError> {
+        self.inner.get_series(base, quote, from, to).await
+    }
+}
+
+/// Serves the last known rates of all providers without touching the network. Only
+/// rates of `date` are considered, or the latest rates if no date is given.
+pub struct OfflineProvider {
+    cache: Arc<Mutex<RateCache>>,
+    date: Option<NaiveDate>,
+}
+
+impl OfflineProvider {
+    pub fn new(cache: Arc<Mutex<RateCache>>, date: Option<NaiveDate>) -> Self {
+        Self { cache, date }
+    }
+}
+
+#[async_trait]
+impl Provider for OfflineProvider {
+    fn get_name(&self) -> String {
+        String::from("cache")
+    }
+    fn supports(&self, symbol: &Symbol) -> bool {
+        self.cache
+            .lock()
+            .unwrap()
+            .rates
+            .values()
+            .any(|r| r.date == self.date && (r.base == *symbol || r.quote == *symbol))
+    }
+    fn supports_pair(&self, base: &Symbol, quote: &Symbol) -> bool {
+        self.cache
+            .lock()
+            .unwrap()
+            .latest(*base, *quote, self.date)
+            .is_some()
+    }
+    fn supports_date(&self, date: &NaiveDate) -> Result<(), ProviderError> {
+        match self.date == Some(*date) {
+            true => Ok(()),
+            _ => Err(ProviderError::Unsupported(format!(
+                "Offline provider only serves rates of {:?}",
+                self.date
+            ))),
+        }
+    }
+    fn build_url(
+        &self,
+        _base: &Symbol,
+        _quote: &Symbol,
+        _date: Option<NaiveDate>,
+    ) -> Result<Url, ProviderError> {
+        Err(ProviderError::Other(anyhow!(
+            "Offline provider does not use the network"
+        )))
+    }
+    fn parse_rate_from_response(
+        &self,
+        _quote: &Symbol,
+        _response: &str,
+    ) -> Result<Decimal, ProviderError> {
+        Err(ProviderError::Other(anyhow!(
+            "Offline provider does not use the network"
+        )))
+    }
+    async fn get_rate(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Decimal, ProviderError> {
+        self.cache
+            .lock()
+            .unwrap()
+            .latest(base, quote, date)
+            .map(|(rate, _)| rate)
+            .ok_or_else(|| {
+                ProviderError::Unsupported(format!("No cached rate from {} to {}", base, quote))
+            })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats a duration coarsely, e.g. `42s`, `5m` or `3h`.
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_age, OfflineProvider, RateCache, SessionProvider, SessionRates};
+    use crate::currency::Symbol;
+    use crate::providers::provider::Provider;
+    use crate::routing::{Graph, MAX_LEGS};
+
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+    use std::env;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn sym(code: &str) -> Symbol {
+        Symbol::from_str(code).unwrap()
+    }
+
+    #[test]
+    fn serves_rates_younger_than_max_age() {
+        let mut cache = RateCache::default();
+        cache.insert(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            None,
+            Decimal::new(11, 1),
+        );
+
+        let fresh = cache.get(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            None,
+            Duration::from_secs(60),
+        );
+        let expired = cache.get(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            None,
+            Duration::from_secs(0),
+        );
+
+        assert_eq!(fresh.map(|r| r.rate), Some(Decimal::new(11, 1)));
+        assert!(
+            expired.is_none(),
+            "Rates older than max age must not be served"
+        );
+    }
+
+    #[test]
+    fn distinguishes_providers_and_pairs() {
+        let mut cache = RateCache::default();
+        cache.insert(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            None,
+            Decimal::new(11, 1),
+        );
+
+        let max_age = Duration::from_secs(60);
+
+        assert!(cache
+            .get("other", sym("EUR"), sym("USD"), None, max_age)
+            .is_none());
+        assert!(cache
+            .get("provider", sym("USD"), sym("EUR"), None, max_age)
+            .is_none());
+    }
+
+    #[test]
+    fn replaces_existing_rates() {
+        let mut cache = RateCache::default();
+        cache.insert(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            None,
+            Decimal::new(11, 1),
+        );
+        cache.insert(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            None,
+            Decimal::new(12, 1),
+        );
+
+        let rate = cache.get(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            None,
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(rate.map(|r| r.rate), Some(Decimal::new(12, 1)));
+        assert_eq!(cache.rates.len(), 1);
+    }
+
+    #[test]
+    fn persists_rates() {
+        let path = env::temp_dir()
+            .join(format!("currency-converter-test-{}", std::process::id()))
+            .join("rates.json");
+        let mut cache = RateCache::load(Some(path.clone()));
+        cache.insert(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            None,
+            Decimal::new(11, 1),
+        );
+
+        cache.save().unwrap();
+        let loaded = RateCache::load(Some(path.clone()));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert_eq!(loaded.rates, cache.rates);
+    }
+
+    #[test]
+    fn leaves_out_stale_latest_rates_when_saving() {
+        let path = env::temp_dir()
+            .join(format!("currency-converter-stale-{}", std::process::id()))
+            .join("rates.json");
+        let date = NaiveDate::from_ymd_opt(2020, 3, 15).unwrap();
+        let mut cache = RateCache::load(Some(path.clone()));
+        cache.insert("stale", sym("EUR"), sym("USD"), None, Decimal::new(11, 1));
+        cache.insert("fresh", sym("EUR"), sym("USD"), None, Decimal::new(12, 1));
+        cache.insert(
+            "old",
+            sym("EUR"),
+            sym("USD"),
+            Some(date),
+            Decimal::new(13, 1),
+        );
+        cache
+            .rates
+            .values_mut()
+            .filter(|r| r.provider != "fresh")
+            .for_each(|r| r.fetched_at -= 365 * 86400);
+
+        cache.save().unwrap();
+        let loaded = RateCache::load(Some(path.clone()));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.count("stale"), 0);
+        assert_eq!(loaded.count("old"), 1);
+    }
+
+    #[test]
+    fn expires_latest_but_keeps_historical_rates() {
+        let date = NaiveDate::from_ymd_opt(2020, 3, 15).unwrap();
+        let mut cache = RateCache::default();
+        cache.insert(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            None,
+            Decimal::new(11, 1),
+        );
+        cache.insert(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            Some(date),
+            Decimal::new(12, 1),
+        );
+
+        cache.expire_latest();
+
+        assert_eq!(cache.count("provider"), 1);
+        assert!(cache.rates.values().all(|r| r.date == Some(date)));
+    }
+
+    #[test]
+    fn serves_historical_rates_regardless_of_age() {
+        let date = NaiveDate::from_ymd_opt(2020, 3, 15).unwrap();
+        let mut cache = RateCache::default();
+        cache.insert(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            Some(date),
+            Decimal::new(11, 1),
+        );
+        cache.rates.values_mut().for_each(|r| r.fetched_at -= 86400);
+
+        let rate = cache.get(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            Some(date),
+            Duration::from_secs(60),
+        );
+        let latest = cache.get(
+            "provider",
+            sym("EUR"),
+            sym("USD"),
+            None,
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(rate.map(|r| r.rate), Some(Decimal::new(11, 1)));
+        assert!(
+            latest.is_none(),
+            "Historical rates must not be served as latest rates"
+        );
+    }
+
+    #[test]
+    fn derives_latest_rate_from_inverse() {
+        let mut cache = RateCache::default();
+        cache.insert("provider", sym("USD"), sym("EUR"), None, Decimal::new(5, 1));
+
+        let latest = cache
+            .latest(sym("EUR"), sym("USD"), None)
+            .map(|(rate, _)| rate);
+
+        assert_eq!(latest, Some(Decimal::new(2, 0)));
+    }
+
+    #[test]
+    fn prefers_most_recent_rate() {
+        let mut cache = RateCache::default();
+        cache.insert("old", sym("EUR"), sym("USD"), None, Decimal::new(11, 1));
+        cache.insert("new", sym("EUR"), sym("USD"), None, Decimal::new(12, 1));
+        cache
+            .rates
+            .values_mut()
+            .filter(|r| r.provider == "old")
+            .for_each(|r| r.fetched_at -= 60);
+
+        let latest = cache
+            .latest(sym("EUR"), sym("USD"), None)
+            .map(|(rate, _)| rate);
+
+        assert_eq!(latest, Some(Decimal::new(12, 1)));
+    }
+
+    #[tokio::test]
+    async fn derives_cross_rates_offline() {
+        let mut cache = RateCache::default();
+        cache.insert("fiat", sym("EUR"), sym("USD"), None, Decimal::new(2, 0));
+        cache.insert("crypto", sym("ETH"), sym("USD"), None, Decimal::new(4, 0));
+        let providers: Vec<Box<dyn Provider>> = vec![Box::new(OfflineProvider::new(
+            Arc::new(Mutex::new(cache)),
+            None,
+        ))];
+        let graph = Graph::new(&providers, &[sym("EUR"), sym("USD"), sym("ETH")]);
+
+        let routes = graph.find_routes(sym("EUR"), sym("ETH"), MAX_LEGS);
+        let rate = routes[0].get_rate(&providers, None).await.ok();
+
+        assert_eq!(routes.len(), 1);
+        assert_eq!(rate, Some(Decimal::new(5, 1)));
+    }
+
+    #[tokio::test]
+    async fn reuses_session_rates_until_cleared() {
+        let mut cache = RateCache::default();
+        cache.insert("provider", sym("EUR"), sym("USD"), None, Decimal::new(2, 0));
+        let cache = Arc::new(Mutex::new(cache));
+        let session = Arc::new(Mutex::new(SessionRates::default()));
+        let provider = SessionProvider::new(
+            Box::new(OfflineProvider::new(cache.clone(), None)),
+            session.clone(),
+            Some(cache.clone()),
+        );
+
+        let fetched = provider.get_rate(sym("EUR"), sym("USD"), None).await.ok();
+        cache.lock().unwrap().expire_latest();
+        let reused = provider.get_rate(sym("EUR"), sym("USD"), None).await.ok();
+        session.lock().unwrap().clear();
+        let cleared = provider.get_rate(sym("EUR"), sym("USD"), None).await;
+
+        assert_eq!(fetched, Some(Decimal::new(2, 0)));
+        assert_eq!(reused, fetched);
+        assert!(cleared.is_err());
+        assert!(session.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn formats_age() {
+        assert_eq!(format_age(Duration::from_secs(42)), "42s");
+        assert_eq!(format_age(Duration::from_secs(300)), "5m");
+        assert_eq!(format_age(Duration::from_secs(3 * 3600 + 5)), "3h");
+        assert_eq!(format_age(Duration::from_secs(2 * 86400)), "2d");
+    }
+}
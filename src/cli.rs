@@ -1,12 +1,19 @@
 use anyhow::*;
+use chrono::NaiveDate;
 use clap::{App, Arg};
+use rust_decimal::Decimal;
 
-use std::convert::TryInto;
 use std::str::FromStr;
 
-use crate::currency::{Currency, Symbol, SymbolPair};
+use currency_converter::aggregation::AGGREGATIONS;
+use currency_converter::amount::Amount;
+use currency_converter::currency::{Conversion, Currency, Symbol, SymbolPair};
+use currency_converter::expression::{self, Expr};
+use currency_converter::output::FORMATS;
 
-const SEPERATORS: &[&str] = &["in", "as", "into", "to", ">", "->", "-->"];
+pub const SEPERATORS: &[&str] = &["in", "as", "into", "to", ">", "->", "-->"];
+const DATE_KEYWORD: &str = "on";
+const DATE_FORMAT: &str = "%Y-%m-%d";
 
 pub fn build_cli() -> App<'static> {
     App::new("Currency Converter")
@@ -36,12 +43,145 @@ pub fn build_cli() -> App<'static> {
                 .short('r')
                 .long("--raw"),
         )
+        .arg(
+            Arg::with_name("output")
+                .about("Output format")
+                .takes_value(true)
+                .short('o')
+                .long("--output")
+                .possible_values(FORMATS)
+                .conflicts_with("raw"),
+        )
         .arg(
             Arg::with_name("stats")
                 .about("Show conversion statistics")
                 .short('s')
                 .long("--stats"),
         )
+        .arg(
+            Arg::with_name("aggregate")
+                .about("How the rates of several routes are combined")
+                .takes_value(true)
+                .short('a')
+                .long("--aggregate")
+                .possible_values(AGGREGATIONS),
+        )
+        .arg(
+            Arg::with_name("min-providers")
+                .about("Fail unless rates come from this many independent providers")
+                .takes_value(true)
+                .long("--min-providers"),
+        )
+        .arg(
+            Arg::with_name("max-spread")
+                .about("Fail if rates differ by more than this, e.g. '0.5%' or '50bps'")
+                .takes_value(true)
+                .long("--max-spread"),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .about("Seconds a single request may take before it is retried")
+                .takes_value(true)
+                .long("--timeout"),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .about("How often requests failing transiently are retried")
+                .takes_value(true)
+                .long("--retries"),
+        )
+        .arg(
+            Arg::with_name("deadline")
+                .about(
+                    "Seconds after which pending requests are given up and partial results shown",
+                )
+                .takes_value(true)
+                .long("--deadline"),
+        )
+        .arg(
+            Arg::with_name("quorum")
+                .about("Use the first routes to answer for each currency, dropping the others")
+                .takes_value(true)
+                .long("--quorum"),
+        )
+        .arg(
+            Arg::with_name("fastest")
+                .about("Use the first route to answer for each currency, like --quorum 1")
+                .long("--fastest")
+                .conflicts_with("quorum"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .about("Do not show the progress of requests")
+                .short('q')
+                .long("--quiet"),
+        )
+        .arg(
+            Arg::with_name("date")
+                .about("Convert with the rates of a past day, e.g. '2020-03-15'")
+                .takes_value(true)
+                .short('d')
+                .long("--date"),
+        )
+        .arg(
+            Arg::with_name("interactive")
+                .about("Answer conversions typed at a prompt, see ':help'")
+                .short('i')
+                .long("--interactive")
+                .conflicts_with_all(&["batch", "from"]),
+        )
+        .arg(
+            Arg::with_name("batch")
+                .about("Convert every line of a file, or of stdin if '-'")
+                .takes_value(true)
+                .short('b')
+                .long("--batch")
+                .conflicts_with_all(&["date", "from"]),
+        )
+        .arg(
+            Arg::with_name("into")
+                .about("Target currency of batch lines without one, e.g. CSV rows")
+                .takes_value(true)
+                .long("--into")
+                .requires("batch"),
+        )
+        .arg(
+            Arg::with_name("from")
+                .about("Show the daily rates since this day instead of converting")
+                .takes_value(true)
+                .long("--from")
+                .conflicts_with_all(&["date", "offline"]),
+        )
+        .arg(
+            Arg::with_name("to")
+                .about("Last day of the rates shown with --from, defaults to today")
+                .takes_value(true)
+                .long("--to")
+                .requires("from"),
+        )
+        .arg(
+            Arg::with_name("no-cache")
+                .about("Ignore cached rates and fetch fresh ones")
+                .long("--no-cache"),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .about("Convert with the last known rates without using the network")
+                .long("--offline")
+                .conflicts_with("no-cache"),
+        )
+        .arg(
+            Arg::with_name("max-age")
+                .about("Reuse cached rates younger than this many seconds")
+                .takes_value(true)
+                .long("--max-age"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .about("Config file with custom currencies and aliases")
+                .takes_value(true)
+                .long("--config"),
+        )
         .arg(
             Arg::with_name("access-key-fixer")
                 .about("Enables the Fixer.io API")
@@ -64,7 +204,7 @@ fn partition_words_by(seperators: &'static [&'static str]) -> impl Fn(Words) ->
             |(mut found_seperator, mut pre, mut post), x| {
                 if found_seperator {
                     post.push(*x);
-                } else if seperators.contains(&x) {
+                } else if seperators.contains(x) {
                     found_seperator = true;
                 } else {
                     pre.push(*x);
@@ -78,27 +218,158 @@ fn partition_words_by(seperators: &'static [&'static str]) -> impl Fn(Words) ->
     }
 }
 
-pub fn parse_currencies(words: Vec<&str>) -> Result<SymbolPair, anyhow::Error> {
+pub fn parse_date(input: &str) -> Result<NaiveDate, anyhow::Error> {
+    NaiveDate::parse_from_str(input, DATE_FORMAT)
+        .with_context(|| format!("Invalid date '{}', expected YYYY-MM-DD", input))
+}
+
+/// Splits a trailing date like `on 2020-03-15` off the currency words.
+pub fn split_date(mut words: Words) -> Result<(Words, Option<NaiveDate>), anyhow::Error> {
+    match words.as_slice() {
+        [.., keyword, date] if *keyword == DATE_KEYWORD => {
+            let date = parse_date(date)?;
+            words.truncate(words.len() - 2);
+            Ok((words, Some(date)))
+        }
+        _ => Ok((words, None)),
+    }
+}
+
+/// Parses words like `turkish lira in usd, gbp` into a base and one or more quote
+/// currencies. Quote currencies are separated by commas. The base currency may be known
+/// from the amount already, like for `$100 in eur`.
+pub fn parse_conversion(
+    words: Vec<&str>,
+    base: Option<Symbol>,
+) -> Result<Conversion, anyhow::Error> {
     let (pre, post) = partition_words_by(SEPERATORS)(words);
 
-    let [base, quote]: [Symbol; 2] = [pre, post]
-        .iter()
-        .map(|l| l.join(" "))
-        .map(|s| Currency::from_str(&s))
-        .map(|r| r.map(|c| c.symbol))
-        .collect::<Result<Vec<Symbol>, _>>()?
-        .as_slice()
-        .try_into()
-        .unwrap();
-
-    Ok(SymbolPair { base, quote })
+    let base = match (base, pre.is_empty()) {
+        (Some(base), true) => base,
+        (Some(base), _) => {
+            let named = Currency::from_str(&pre.join(" "))?.symbol;
+            if named != base {
+                return Err(anyhow!(
+                    "Amount is in {} but {} is given as input currency",
+                    base,
+                    named
+                ));
+            }
+            base
+        }
+        (None, _) => Currency::from_str(&pre.join(" "))?.symbol,
+    };
+    let mut quotes: Vec<Symbol> = vec![];
+    for name in post.join(" ").split(',').map(str::trim) {
+        let quote = Currency::from_str(name)?.symbol;
+        if !quotes.contains(&quote) {
+            quotes.push(quote);
+        }
+    }
+
+    Ok(Conversion { base, quotes })
+}
+
+/// A conversion request like `100 eur in usd, gbp on 2020-03-15`.
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    pub amount: Decimal,
+    pub conversion: Conversion,
+    pub date: Option<NaiveDate>,
+}
+
+/// Parses the words of a request. The amount may be left out and defaults to 1, e.g.
+/// for `eur in usd --from 2020-01-01`.
+pub fn parse_query(mut words: Words) -> Result<Query, anyhow::Error> {
+    let amount = match words.first() {
+        Some(word) if word.contains(|c: char| c.is_ascii_digit()) => {
+            let amount = Amount::from_str(word)?;
+            words.remove(0);
+            amount
+        }
+        _ => Amount {
+            value: Decimal::new(1, 0),
+            currency: None,
+        },
+    };
+    let (words, date) = split_date(words)?;
+    let conversion =
+        parse_conversion(words, amount.currency).context("Failed to parse currency string")?;
+
+    if conversion.quotes.contains(&conversion.base) {
+        return Err(anyhow!("Input and output currency are identical."));
+    }
+
+    Ok(Query {
+        amount: amount.value,
+        conversion,
+        date,
+    })
+}
+
+/// An arithmetic request like `120 usd + 35 eur in try on 2020-03-15`.
+#[derive(Debug, PartialEq)]
+pub struct ExpressionQuery {
+    pub expression: Expr,
+    pub quote: Symbol,
+    pub date: Option<NaiveDate>,
+}
+
+/// Whether a request is arithmetic like `120 usd + 35 eur in try`. Only the words before
+/// the separator count, so that dates like `on 2020-03-15` and separators like `->` are
+/// not taken for operators.
+pub fn is_expression_query(words: &[&str]) -> bool {
+    let (pre, _) = partition_words_by(SEPERATORS)(words.to_vec());
+    expression::is_expression(&pre)
+}
+
+pub fn parse_expression_query(words: Words) -> Result<ExpressionQuery, anyhow::Error> {
+    let (words, date) = split_date(words)?;
+    let (pre, post) = partition_words_by(SEPERATORS)(words);
+    if post.is_empty() {
+        return Err(anyhow!("Expected a target currency, e.g. '... in usd'"));
+    }
+
+    let expression = expression::parse(&pre.join(" ")).context("Failed to parse expression")?;
+    let quote = Currency::from_str(&post.join(" "))
+        .context("Expressions can only be converted into a single target currency")?
+        .symbol;
+
+    Ok(ExpressionQuery {
+        expression,
+        quote,
+        date,
+    })
+}
+
+pub fn parse_currencies(
+    words: Vec<&str>,
+    base: Option<Symbol>,
+) -> Result<SymbolPair, anyhow::Error> {
+    let conversion = parse_conversion(words, base)?;
+
+    match conversion.quotes.as_slice() {
+        [quote] => Ok(SymbolPair {
+            base: conversion.base,
+            quote: *quote,
+        }),
+        _ => Err(anyhow!("Expected a single target currency")),
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::is_expression_query;
+    use super::parse_conversion;
     use super::parse_currencies;
+    use super::parse_expression_query;
+    use super::parse_query;
     use super::partition_words_by;
-    use crate::currency::{Symbol, SymbolPair};
+    use super::split_date;
+    use chrono::NaiveDate;
+    use currency_converter::currency::{Conversion, Symbol, SymbolPair};
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
 
     #[test]
     fn basic_partition() {
@@ -153,10 +424,10 @@ mod tests {
     #[test]
     fn basic_currency_parsing() {
         let expected_pair = SymbolPair {
-            base: Symbol::USD,
-            quote: Symbol::EUR,
+            base: Symbol::from_str("USD").unwrap(),
+            quote: Symbol::from_str("EUR").unwrap(),
         };
-        let option = parse_currencies(vec!["usd", "in", "eur"]).ok();
+        let option = parse_currencies(vec!["usd", "in", "eur"], None).ok();
 
         assert_eq!(option, Some(expected_pair));
     }
@@ -164,10 +435,10 @@ mod tests {
     #[test]
     fn compound_currency_parsing() {
         let expected_pair = SymbolPair {
-            base: Symbol::TL,
-            quote: Symbol::EUR,
+            base: Symbol::from_str("TRY").unwrap(),
+            quote: Symbol::from_str("EUR").unwrap(),
         };
-        let option = parse_currencies(vec!["turkish", "lira", "in", "eur"]).ok();
+        let option = parse_currencies(vec!["turkish", "lira", "in", "eur"], None).ok();
 
         assert_eq!(option, Some(expected_pair));
     }
@@ -175,31 +446,170 @@ mod tests {
     #[test]
     fn compound_currency_parsing2() {
         let expected_pair = SymbolPair {
-            base: Symbol::TL,
-            quote: Symbol::TL,
+            base: Symbol::from_str("TRY").unwrap(),
+            quote: Symbol::from_str("TRY").unwrap(),
         };
-        let option = parse_currencies(vec!["turkish", "lira", "in", "turkish", "lira"]).ok();
+        let option = parse_currencies(vec!["turkish", "lira", "in", "turkish", "lira"], None).ok();
 
         assert_eq!(option, Some(expected_pair));
     }
 
     #[test]
     fn no_base_currency_parsing() {
-        let result = parse_currencies(vec!["invalid", "in", "turkish", "lira"]);
+        let result = parse_currencies(vec!["invalid", "in", "turkish", "lira"], None);
 
         assert!(result.is_err());
     }
 
     #[test]
     fn no_quote_currency_parsing() {
-        let result = parse_currencies(vec!["usd", "in"]);
+        let result = parse_currencies(vec!["usd", "in"], None);
 
         assert!(result.is_err());
     }
 
     #[test]
     fn no_seperator_currency_parsing() {
-        let result = parse_currencies(vec!["usd", "foo", "eur"]);
+        let result = parse_currencies(vec!["usd", "foo", "eur"], None);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn multiple_quote_currency_parsing() {
+        let expected = Conversion {
+            base: Symbol::from_str("EUR").unwrap(),
+            quotes: vec![
+                Symbol::from_str("USD").unwrap(),
+                Symbol::from_str("GBP").unwrap(),
+                Symbol::from_str("TRY").unwrap(),
+            ],
+        };
+        let option =
+            parse_conversion(vec!["eur", "in", "usd,", "gbp,", "turkish", "lira"], None).ok();
+
+        assert_eq!(option, Some(expected));
+    }
+
+    #[test]
+    fn multiple_quote_currencies_fail_pair_parsing() {
+        let result = parse_currencies(vec!["eur", "in", "usd,", "gbp"], None);
+
+        assert!(result.is_err());
+    }
+
+    // parse_query tests
+    #[test]
+    fn parses_query_with_amount_and_date() {
+        let query = parse_query(vec!["12.5", "eur", "in", "usd", "on", "2020-03-15"]).unwrap();
+
+        assert_eq!(query.amount, Decimal::new(125, 1));
+        assert_eq!(
+            query.conversion.quotes,
+            vec![Symbol::from_str("USD").unwrap()]
+        );
+        assert_eq!(query.date, NaiveDate::from_ymd_opt(2020, 3, 15));
+    }
+
+    #[test]
+    fn parses_query_without_amount() {
+        let query = parse_query(vec!["eur", "in", "usd"]).unwrap();
+
+        assert_eq!(query.amount, Decimal::new(1, 0));
+    }
+
+    #[test]
+    fn parses_query_with_currency_sign_and_suffix() {
+        let query = parse_query(vec!["$1.5k", "in", "eur"]).unwrap();
+
+        assert_eq!(query.amount, Decimal::new(1500, 0));
+        assert_eq!(query.conversion.base, Symbol::from_str("USD").unwrap());
+    }
+
+    #[test]
+    fn fails_query_with_invalid_amount_or_conflicting_currency() {
+        for words in &["1,23,4 eur in usd", "100€ usd in gbp", "12x eur in usd"] {
+            let result = parse_query(words.split(' ').collect());
+
+            assert!(result.is_err(), "{} should not parse", words);
+        }
+    }
+
+    #[test]
+    fn fails_query_with_identical_currencies() {
+        let result = parse_query(vec!["1", "eur", "in", "usd,", "eur"]);
+
+        assert!(result.is_err());
+    }
+
+    // is_expression_query tests
+    #[test]
+    fn detects_expression_queries() {
+        let detect = |words: &str| is_expression_query(&words.split(' ').collect::<Vec<_>>());
+
+        assert!(detect("120 usd + 35 eur in try"));
+        assert!(detect("120 usd + 35 eur in try on 2020-03-15"));
+        assert!(detect("3*19.99 eur -> usd"));
+        assert!(!detect("5 usd in eur on 2020-03-15"));
+        assert!(!detect("5 usd in eur, gbp on 2020-03-15"));
+        assert!(!detect("5 usd -> eur"));
+        assert!(!detect("5 usd --> eur on 2020-03-15"));
+    }
+
+    #[test]
+    fn parses_dated_queries_as_conversions() {
+        let words = "5 usd in eur, gbp on 2020-03-15"
+            .split(' ')
+            .collect::<Vec<_>>();
+        assert!(!is_expression_query(&words));
+
+        let query = parse_query(words).unwrap();
+
+        assert_eq!(query.conversion.quotes.len(), 2);
+        assert_eq!(query.date, NaiveDate::from_ymd_opt(2020, 3, 15));
+    }
+
+    // parse_expression_query tests
+    #[test]
+    fn parses_expression_query() {
+        let words = "120 usd + 35 eur - 10 gbp in tl on 2020-03-15";
+
+        let query = parse_expression_query(words.split(' ').collect()).unwrap();
+
+        assert_eq!(query.expression.amounts().len(), 3);
+        assert_eq!(query.quote, Symbol::from_str("TRY").unwrap());
+        assert_eq!(query.date, NaiveDate::from_ymd_opt(2020, 3, 15));
+    }
+
+    #[test]
+    fn fails_expression_query_without_single_target() {
+        for words in &["1 usd + 1 eur", "1 usd + 1 eur in gbp, chf"] {
+            let result = parse_expression_query(words.split(' ').collect());
+
+            assert!(result.is_err(), "{} should not parse", words);
+        }
+    }
+
+    // split_date tests
+    #[test]
+    fn splits_trailing_date() {
+        let (words, date) = split_date(vec!["eur", "in", "usd", "on", "2020-03-15"]).unwrap();
+
+        assert_eq!(words, vec!["eur", "in", "usd"]);
+        assert_eq!(date, Some(NaiveDate::from_ymd_opt(2020, 3, 15).unwrap()));
+    }
+
+    #[test]
+    fn keeps_words_without_date() {
+        let (words, date) = split_date(vec!["eur", "in", "usd"]).unwrap();
+
+        assert_eq!(words, vec!["eur", "in", "usd"]);
+        assert_eq!(date, None);
+    }
+
+    #[test]
+    fn fails_on_invalid_date() {
+        let result = split_date(vec!["eur", "in", "usd", "on", "15.03.2020"]);
 
         assert!(result.is_err());
     }
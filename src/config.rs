@@ -0,0 +1,304 @@
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::currency::CurrencyType;
+use crate::providers::http::{DEFAULT_RETRIES, DEFAULT_TIMEOUT};
+
+const CONFIG_ENV_VAR: &str = "CURRENCY_CONVERTER_CONFIG";
+const CONFIG_DIR: &str = "currency-converter";
+const CONFIG_FILE: &str = "config.toml";
+
+/// User configuration, read from `$XDG_CONFIG_HOME/currency-converter/config.toml` by
+/// default. All sections are optional.
+///
+/// ```toml
+/// [aliases]
+/// lira = "TRY"
+///
+/// [overrides]
+/// "$" = "CAD"
+///
+/// [[currencies]]
+/// code = "ACME"
+/// name = "Acme Token"
+/// sign = "Ⓐ"
+/// type = "crypto"
+/// aliases = ["acme coin"]
+///
+/// [cache]
+/// max_age = 600
+///
+/// [cache.providers]
+/// "coinmarketcap.com" = 3600
+///
+/// [weights]
+/// "fixer.io" = 2.0
+///
+/// [network]
+/// timeout = 10
+/// retries = 2
+/// deadline = 30
+/// proxy = "http://proxy.example.com:3128"
+/// no_proxy = ["localhost", "internal.example.com"]
+/// ca_bundle = "/etc/ssl/certs/internal-root.pem"
+/// user_agent = "invoicing/1.0"
+/// tls_min_version = "1.2"
+///
+/// [network.timeouts]
+/// "coinmarketcap.com" = 5
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Additional currencies. Entries with the code of a known currency replace it.
+    pub currencies: Vec<CurrencyConfig>,
+    /// Additional guesses, mapping an alias to a currency code. Aliases must not
+    /// collide with existing guesses.
+    pub aliases: HashMap<String, String>,
+    /// Like `aliases`, but allowed to replace existing guesses.
+    pub overrides: HashMap<String, String>,
+    pub cache: CacheConfig,
+    /// Weights of providers by name for `--aggregate weighted-mean`, 1 by default
+    pub weights: HashMap<String, f64>,
+    pub network: NetworkConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Cache file, defaults to `$XDG_CACHE_HOME/currency-converter/rates.json`
+    pub path: Option<PathBuf>,
+    /// Seconds for which fetched rates are reused
+    pub max_age: u64,
+    /// Like `max_age`, but for individual providers by name
+    pub providers: HashMap<String, u64>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            path: None,
+            max_age: 600,
+            providers: HashMap::new(),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn max_age(&self, provider: &str) -> Duration {
+        Duration::from_secs(*self.providers.get(provider).unwrap_or(&self.max_age))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Seconds a single request may take
+    pub timeout: u64,
+    /// Like `timeout`, but for individual providers by name
+    pub timeouts: HashMap<String, u64>,
+    /// Retries of requests failing transiently
+    pub retries: u32,
+    /// Seconds after which pending requests are given up and partial results shown
+    pub deadline: Option<u64>,
+    /// Proxy of all requests, like `http://proxy.example.com:3128`. Unless set, the
+    /// `HTTP_PROXY` and `HTTPS_PROXY` environment variables are honored.
+    pub proxy: Option<String>,
+    /// Hosts reached without the proxy, including their subdomains. `*` matches all.
+    /// Applies to the proxies of the environment as well.
+    pub no_proxy: Vec<String>,
+    /// PEM file with root certificates trusted in addition to the system's
+    pub ca_bundle: Option<PathBuf>,
+    /// Sent with every request instead of `currency-converter/<version>`
+    pub user_agent: Option<String>,
+    /// Oldest accepted TLS version, like `1.2`
+    pub tls_min_version: Option<String>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            timeout: DEFAULT_TIMEOUT.as_secs(),
+            timeouts: HashMap::new(),
+            retries: DEFAULT_RETRIES,
+            deadline: None,
+            proxy: None,
+            no_proxy: Vec::new(),
+            ca_bundle: None,
+            user_agent: None,
+            tls_min_version: None,
+        }
+    }
+}
+
+impl NetworkConfig {
+    pub fn timeout(&self, provider: &str) -> Duration {
+        Duration::from_secs(*self.timeouts.get(provider).unwrap_or(&self.timeout))
+    }
+
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline.map(Duration::from_secs)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CurrencyConfig {
+    pub code: String,
+    pub name: String,
+    #[serde(default)]
+    pub sign: String,
+    pub minor_units: Option<u8>,
+    #[serde(rename = "type", default = "default_currency_type")]
+    pub currency_type: CurrencyType,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+fn default_currency_type() -> CurrencyType {
+    CurrencyType::Fiat
+}
+
+impl Config {
+    /// Location of the config file, unless overridden on the command line.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os(CONFIG_ENV_VAR)
+            .map(PathBuf::from)
+            .or_else(|| dirs::config_dir().map(|d| d.join(CONFIG_DIR).join(CONFIG_FILE)))
+    }
+
+    /// Loads the config from `path` if given. Otherwise, the default location is tried
+    /// and a missing file results in an empty config.
+    pub fn load(path: Option<&Path>) -> Result<Config, anyhow::Error> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Config::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        content
+            .parse()
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        toml::from_str(s).map_err(|e| anyhow!("{}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use crate::currency::CurrencyType;
+
+    use std::time::Duration;
+
+    #[test]
+    fn parses_config() {
+        let input = r#"
+            [aliases]
+            lira = "TRY"
+            buck = "USD"
+
+            [overrides]
+            "$" = "CAD"
+
+            [[currencies]]
+            code = "ACME"
+            name = "Acme Token"
+            sign = "Ⓐ"
+            type = "crypto"
+            aliases = ["acme coin"]
+        "#;
+
+        let config = input.parse::<Config>().unwrap();
+
+        assert_eq!(config.aliases.get("lira"), Some(&String::from("TRY")));
+        assert_eq!(config.overrides.get("$"), Some(&String::from("CAD")));
+        assert_eq!(config.currencies.len(), 1);
+        assert_eq!(config.currencies[0].currency_type, CurrencyType::Crypto);
+        assert_eq!(config.currencies[0].aliases, vec!["acme coin"]);
+    }
+
+    #[test]
+    fn parses_empty_config() {
+        let config = "".parse::<Config>().unwrap();
+
+        assert!(config.currencies.is_empty());
+        assert!(config.aliases.is_empty());
+        assert_eq!(config.cache.max_age("fixer.io"), Duration::from_secs(600));
+    }
+
+    #[test]
+    fn parses_per_provider_cache_max_age() {
+        let input = r#"
+            [cache]
+            max_age = 60
+
+            [cache.providers]
+            "coinmarketcap.com" = 3600
+        "#;
+
+        let config = input.parse::<Config>().unwrap();
+
+        assert_eq!(config.cache.max_age("fixer.io"), Duration::from_secs(60));
+        assert_eq!(
+            config.cache.max_age("coinmarketcap.com"),
+            Duration::from_secs(3600)
+        );
+    }
+
+    #[test]
+    fn parses_network_settings() {
+        let input = r#"
+            [network]
+            timeout = 5
+            deadline = 20
+
+            [network.timeouts]
+            "coinmarketcap.com" = 2
+        "#;
+
+        let config = input.parse::<Config>().unwrap();
+
+        assert_eq!(config.network.timeout("fixer.io"), Duration::from_secs(5));
+        assert_eq!(
+            config.network.timeout("coinmarketcap.com"),
+            Duration::from_secs(2)
+        );
+        assert_eq!(config.network.retries, 2);
+        assert_eq!(config.network.deadline(), Some(Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn parses_provider_weights() {
+        let input = "[weights]\n\"fixer.io\" = 2.5\n\"coinmarketcap.com\" = 1";
+
+        let config = input.parse::<Config>().unwrap();
+
+        assert_eq!(config.weights.get("fixer.io"), Some(&2.5));
+        assert_eq!(config.weights.get("coinmarketcap.com"), Some(&1.0));
+    }
+
+    #[test]
+    fn fails_parsing_unknown_fields() {
+        let config = "[currency]\ncode = \"ACME\"".parse::<Config>();
+
+        assert!(config.is_err(), "Misspelled sections should be reported");
+    }
+}
@@ -0,0 +1,507 @@
+use anyhow::{anyhow, Context};
+use chrono::{NaiveDate, Utc};
+use colored::*;
+use rust_decimal::Decimal;
+
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use currency_converter::aggregation::{parse_spread, Aggregation, Aggregator};
+use currency_converter::cache::{format_age, RateCache, SessionRates};
+use currency_converter::config::Config;
+use currency_converter::output::Status;
+use currency_converter::providers::error::ProviderError;
+use currency_converter::providers::provider::{Provider, Quota};
+use currency_converter::utils::Stats;
+use currency_converter::{Converted, Converter};
+
+use crate::cli::{ExpressionQuery, Query};
+
+/// How conversions are printed, shared by single conversions and the interactive mode.
+pub struct Options {
+    pub precise: bool,
+    pub raw: bool,
+    pub stats: bool,
+    pub json: bool,
+    /// Hides the progress of requests
+    pub quiet: bool,
+}
+
+impl Options {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        Options {
+            precise: matches.is_present("precise"),
+            raw: matches.is_present("raw"),
+            stats: matches.is_present("stats"),
+            json: matches.value_of("output") == Some("json"),
+            quiet: matches.is_present("quiet"),
+        }
+    }
+}
+
+/// Sets up the providers enabled by default or by access keys on the command line,
+/// along with the aggregation, the cache and the network settings of the command line
+/// and the config.
+pub fn build_converter(
+    matches: &clap::ArgMatches,
+    config: &Config,
+    cache: Arc<Mutex<RateCache>>,
+) -> Result<Converter, anyhow::Error> {
+    let seconds = |name: &str, context: &'static str| {
+        matches
+            .value_of(name)
+            .map(u64::from_str)
+            .transpose()
+            .context(context)
+            .map(|s| s.map(Duration::from_secs))
+    };
+
+    let mut aggregator = Aggregator::from_config(config)?;
+    if let Some(aggregation) = matches.value_of("aggregate") {
+        aggregator.aggregation = Aggregation::from_str(aggregation)?;
+    }
+    aggregator.min_providers = matches
+        .value_of("min-providers")
+        .map(usize::from_str)
+        .transpose()
+        .context("Minimum number of providers must be a whole number")?;
+    aggregator.max_spread_bps = matches
+        .value_of("max-spread")
+        .map(parse_spread)
+        .transpose()?;
+
+    let mut cache_config = config.cache.clone();
+    let max_age = match matches.is_present("no-cache") {
+        true => Some(Duration::from_secs(0)),
+        _ => seconds("max-age", "Max age must be a number of seconds")?,
+    };
+    if let Some(max_age) = max_age {
+        cache_config.max_age = max_age.as_secs();
+        cache_config.providers.clear();
+    }
+
+    let options = Options::from_matches(matches);
+    let mut builder = Converter::builder()
+        .exchangeratesapi()
+        .aggregator(aggregator)
+        .cache(cache, cache_config)
+        .offline(matches.is_present("offline"))
+        .network(config.network.clone())
+        .progress(!options.json && !options.quiet);
+    if matches.is_present("interactive") {
+        builder = builder.session(Arc::new(Mutex::new(SessionRates::default())));
+    }
+    if let Some(access_key) = matches.value_of("access-key-fixer") {
+        builder = builder.fixer(access_key);
+    }
+    if let Some(access_key) = matches.value_of("access-key-coinmarketcap") {
+        builder = builder.coinmarketcap(access_key);
+    }
+
+    if let Some(timeout) = seconds("timeout", "Timeout must be a number of seconds")? {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(retries) = matches.value_of("retries") {
+        builder = builder.retries(u32::from_str(retries).context("Retries must be a number")?);
+    }
+    if let Some(deadline) = seconds("deadline", "Deadline must be a number of seconds")? {
+        builder = builder.deadline(deadline);
+    }
+    let quorum = match matches.is_present("fastest") {
+        true => Some(1),
+        _ => matches
+            .value_of("quorum")
+            .map(usize::from_str)
+            .transpose()
+            .context("Quorum must be a positive number")?,
+    };
+    if let Some(quorum) = quorum {
+        builder = builder.quorum(quorum);
+    }
+
+    builder.build()
+}
+
+/// Saves the rates fetched by a conversion, warning if that fails.
+pub fn save_cache(converter: &Converter) {
+    if let Some(cache) = converter.cache() {
+        if let Err(e) = cache.lock().unwrap().save() {
+            eprintln!("Warning: {:#}", e);
+        }
+    }
+}
+
+/// What to do about a provider error, if anything.
+fn guidance(error: &ProviderError) -> Option<&'static str> {
+    match error {
+        ProviderError::InvalidApiKey(_) => Some(
+            "Check the access key passed with --access-key-fixer or --access-key-coinmarketcap.",
+        ),
+        ProviderError::RateLimited(_) => Some(
+            "The provider refuses further requests for now. Try again later, or use cached \
+             rates with --max-age or --offline.",
+        ),
+        ProviderError::Unsupported(_) => Some(
+            "The provider does not serve this, at least not on its current plan. Try other \
+             currencies or another date.",
+        ),
+        ProviderError::Network(_) => Some(
+            "Check your connection and the network settings of the config file, or use cached \
+             rates with --offline.",
+        ),
+        ProviderError::MalformedResponse(_) => Some(
+            "The API responded unexpectedly and may have changed. Please report this at \
+             https://github.com/mohoff/currency-converter/issues.",
+        ),
+        ProviderError::Api { .. } | ProviderError::Other(_) => None,
+    }
+}
+
+/// Prints what to do about the first provider error among the causes of `error`.
+pub fn print_guidance(error: &anyhow::Error) {
+    if let Some(guidance) = ProviderError::find(error).and_then(guidance) {
+        eprintln!("{}", guidance.yellow());
+    }
+}
+
+/// Prints the credits spent on the plans of providers charging them, along with the
+/// quota left and notices of their APIs. Plans about to run out are highlighted.
+pub fn print_usage(providers: &[Box<dyn Provider>]) {
+    let usages = providers
+        .iter()
+        .filter_map(|p| Some((p.get_name(), p.usage()?)))
+        .collect::<Vec<_>>();
+    if usages.is_empty() {
+        return;
+    }
+
+    println!("Usage of plans:");
+    for (name, usage) in usages {
+        let quota = match usage.quota {
+            Some(Quota {
+                remaining,
+                limit: Some(limit),
+            }) => format!(", {}/{} left", remaining, limit),
+            Some(Quota { remaining, .. }) => format!(", {} left", remaining),
+            None => String::from(""),
+        };
+        let line = format!("  {}: {} credits used{}", name, usage.credits, quota);
+        match usage.is_running_out() {
+            true => println!("{}", line.yellow()),
+            _ => println!("{}", line),
+        }
+        if let Some(notice) = &usage.notice {
+            println!("{}", format!("    {}", notice).yellow());
+        }
+    }
+}
+
+/// Prints a notice for every provider without rates of all the dates and fails if none
+/// has them.
+pub fn check_dates(
+    providers: &[Box<dyn Provider>],
+    dates: &[NaiveDate],
+) -> Result<(), anyhow::Error> {
+    let mut providers = providers.iter().collect::<Vec<_>>();
+    for date in dates {
+        providers.retain(|p| {
+            let supported = p.supports_date(date);
+            if let Err(e) = &supported {
+                let message = format!("Skipping {}: {}", p.get_name(), e);
+                eprintln!("{}", message.dimmed());
+            }
+            supported.is_ok()
+        });
+        if providers.is_empty() {
+            return Err(anyhow!("No provider serves rates of {}", date));
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts the amount of a query into each of its quote currencies along all shortest
+/// routes between the providers, and prints the results.
+pub async fn convert(
+    options: &Options,
+    converter: &Converter,
+    query: &Query,
+) -> Result<(), anyhow::Error> {
+    let Query {
+        amount,
+        conversion,
+        date,
+    } = query;
+    let (amount, date) = (*amount, *date);
+    let offline = converter.offline_providers(date);
+    check_dates(
+        offline.as_deref().unwrap_or(converter.providers()),
+        date.as_slice(),
+    )?;
+
+    let conversions = converter
+        .convert_all(amount, conversion.base, &conversion.quotes, date)
+        .await;
+    save_cache(converter);
+
+    // Target currencies without routes are skipped
+    let conversions = conversions?
+        .into_iter()
+        .zip(conversion.quotes.iter())
+        .filter_map(|(converted, quote)| match converted {
+            Ok(converted) => Some(converted),
+            Err(e) => {
+                eprintln!("{}", format!("Skipping {}: {}", quote, e).dimmed());
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let round = |value: Decimal| match options.precise {
+        true => value.normalize(),
+        _ => value.round_dp(2).normalize(),
+    };
+
+    if options.json {
+        let now = Utc::now();
+        let reports = conversions
+            .iter()
+            .map(|c| c.report(round, now))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // A single target currency keeps printing a single object
+        match reports.as_slice() {
+            [report] => println!("{}", serde_json::to_string_pretty(report)?),
+            _ => println!("{}", serde_json::to_string_pretty(&reports)?),
+        }
+        return check_converted(conversions);
+    }
+
+    let date_label = date.map(|d| format!(" (on {})", d)).unwrap_or_default();
+
+    for converted in conversions.iter() {
+        let result = converted.result()?.map(round);
+
+        // Offline answers are labelled with the age of the oldest rate they are based on
+        let offline_label = match converted.offline {
+            true => converted
+                .routes
+                .iter()
+                .filter(|r| r.rate.is_ok())
+                .filter_map(|r| r.cached_age)
+                .max()
+                .map(|age| format!(" (offline, rates up to {} old)", format_age(age))),
+            _ => None,
+        };
+
+        match (options.raw, result) {
+            (true, Some(result)) => println!("{}", result),
+            (true, None) => println!(),
+            (_, result) => println!(
+                "{} {} ⟶  {} {}{}{}",
+                amount,
+                conversion.base.to_string().dimmed(),
+                result
+                    .map(|r| r.to_string().normal())
+                    .unwrap_or_else(|| "<failed>".red()),
+                converted.quote.to_string().dimmed(),
+                date_label.dimmed(),
+                offline_label.unwrap_or_default().yellow()
+            ),
+        }
+
+        if options.stats {
+            print_routes(converted);
+        }
+    }
+    if options.stats {
+        print_usage(converter.providers());
+    }
+
+    check_converted(conversions)
+}
+
+/// Prints the rates of the routes of a conversion and how they were combined.
+fn print_routes(converted: &Converted) {
+    let rates = converted.rates();
+    let show = |value: Option<Decimal>| {
+        value
+            .map(|v| v.normalize().to_string().normal())
+            .unwrap_or_else(|| "<cannot compute>".italic())
+    };
+    let route_statuses = converted.routes.iter().map(|r| {
+        let origin = match r.cached_age {
+            Some(age) => format!(" (cached, {} old)", format_age(age)),
+            None => String::from(""),
+        };
+
+        match (&r.rate, &r.discarded) {
+            (Ok(rate), Some(reason)) => {
+                format!("  {}: {}{}, discarded as {}", r.route, rate, origin, reason).yellow()
+            }
+            (Ok(rate), None) => format!("  {}: {}{}", r.route, rate, origin).green(),
+            (Err(_), _) => match Status::of(&r.rate) {
+                Status::TimedOut => format!("  {}: <timed out>", r.route).yellow(),
+                Status::Dropped => format!("  {}: <dropped>", r.route).dimmed(),
+                _ => format!("  {}: <failed>", r.route).dimmed(),
+            },
+        }
+    });
+
+    println!(
+        "Successfully fetched {}/{} routes:",
+        rates.len(),
+        converted.routes.len()
+    );
+    route_statuses.for_each(|l| println!("{}", l));
+    println!(
+        "Fetched rates: {:?}, σ: {}, sample σ: {}",
+        rates,
+        show(rates.std_deviation()),
+        show(rates.sample_std_deviation())
+    );
+    if let (Some(min), Some(max)) = (rates.minimum(), rates.maximum()) {
+        println!(
+            "Range: {} to {} ({}), spread: {} bps, coefficient of variation: {}",
+            min,
+            max,
+            (max - min).normalize(),
+            show(rates.spread_bps().map(|s| s.round_dp(2))),
+            show(rates.coefficient_of_variation().map(|c| c.round_dp(6)))
+        );
+    }
+    if let Some(rate) = converted.rate {
+        println!("Rate by {}: {}", converted.aggregation, rate);
+    }
+}
+
+/// Fails with the error of the first failed route if no target currency got a rate, once
+/// the conversions were printed along with the statuses of their routes.
+fn check_converted(conversions: Vec<Converted>) -> Result<(), anyhow::Error> {
+    if conversions.iter().any(|c| c.rate.is_some()) {
+        return Ok(());
+    }
+    conversions
+        .into_iter()
+        .try_for_each(|c| c.require_rate().map(|_| ()))
+}
+
+/// Converts every currency of an expression into the target currency along all routes,
+/// like single conversions, and prints the total along with the converted amounts.
+pub async fn evaluate(
+    options: &Options,
+    converter: &Converter,
+    query: &ExpressionQuery,
+) -> Result<(), anyhow::Error> {
+    let ExpressionQuery {
+        expression,
+        quote,
+        date,
+    } = query;
+    let (quote, date) = (*quote, *date);
+    let offline = converter.offline_providers(date);
+    check_dates(
+        offline.as_deref().unwrap_or(converter.providers()),
+        date.as_slice(),
+    )?;
+
+    let evaluated = converter.evaluate(expression, quote, date).await;
+    save_cache(converter);
+    let evaluated = evaluated?;
+
+    let round = |value: Decimal| match options.precise {
+        true => value.normalize(),
+        _ => value.round_dp(2).normalize(),
+    };
+    let report = evaluated.report(round, Utc::now())?;
+
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    if options.raw {
+        println!("{}", report.result);
+        return Ok(());
+    }
+
+    let date_label = date.map(|d| format!(" (on {})", d)).unwrap_or_default();
+    // Offline answers are labelled with the age of the oldest rate they are based on
+    let offline_label = match converter.is_offline() {
+        true => evaluated
+            .conversions
+            .iter()
+            .flat_map(|c| c.routes.iter())
+            .filter(|r| r.rate.is_ok())
+            .filter_map(|r| r.cached_age)
+            .max()
+            .map(|age| format!(" (offline, rates up to {} old)", format_age(age)))
+            .or_else(|| Some(String::from(" (offline)"))),
+        _ => None,
+    };
+    println!(
+        "{} ⟶  {} {}{}{}",
+        expression,
+        report.result,
+        quote.to_string().dimmed(),
+        date_label.dimmed(),
+        offline_label.unwrap_or_default().yellow()
+    );
+    for term in report.terms.iter() {
+        let rate = match options.stats {
+            true => format!(" at {}", term.rate),
+            _ => String::from(""),
+        };
+        let line = format!(
+            "  {} {} ⟶  {} {}{}",
+            term.amount, term.base, term.result, quote, rate
+        );
+        println!("{}", line.dimmed());
+    }
+    if options.stats {
+        for converted in evaluated.conversions.iter() {
+            println!("{} ⟶  {}:", converted.base, quote);
+            print_routes(converted);
+        }
+        print_usage(converter.providers());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_converted;
+    use currency_converter::{Aggregation, Converted, RouteRate, Symbol};
+
+    use anyhow::anyhow;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    #[test]
+    fn fails_without_any_rate() {
+        let converted = Converted {
+            amount: Decimal::new(1, 0),
+            base: Symbol::from_str("EUR").unwrap(),
+            quote: Symbol::from_str("USD").unwrap(),
+            date: None,
+            rate: None,
+            aggregation: Aggregation::Median,
+            offline: false,
+            routes: vec![RouteRate {
+                route: String::from("EUR -(provider)-> USD"),
+                providers: vec![String::from("provider")],
+                rate: Err(anyhow!("Request failed")),
+                discarded: None,
+                cached_age: None,
+            }],
+        };
+
+        let error = check_converted(vec![converted]).unwrap_err();
+
+        assert_eq!(
+            format!("{:#}", error),
+            "No route returned a rate: Request failed"
+        );
+    }
+}
@@ -0,0 +1,1141 @@
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::aggregation::{Aggregation, Aggregator};
+use crate::cache::{CachedProvider, OfflineProvider, RateCache, SessionProvider, SessionRates};
+use crate::config::{CacheConfig, NetworkConfig};
+use crate::currency::{self, Symbol, SymbolPair};
+use crate::expression::Expr;
+use crate::output::{ConversionReport, ExpressionReport, RouteReport, Status, TermReport};
+use crate::progress::Progress;
+use crate::progress_join::{join_quorum, Dropped};
+use crate::providers::coinmarketcap::{self, CoinMarketCapProvider};
+use crate::providers::exchangeratesapi::{self, ExchangeRatesApiProvider};
+use crate::providers::fixer::{self, FixerProvider};
+use crate::providers::http::{build_client, with_deadline, Http};
+use crate::providers::provider::{Provider, Series};
+use crate::routing::{fetch_legs, Graph, Route, Wait, MAX_LEGS};
+use crate::series::merge;
+use crate::utils::Stats;
+
+/// A provider to register, built once the HTTP client is known.
+enum Source {
+    ExchangeRatesApi,
+    Fixer(String),
+    CoinMarketCap(String),
+    Custom(Box<dyn Provider>),
+}
+
+/// Sets up a `Converter`. Nothing but the providers is required.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), anyhow::Error> {
+/// use currency_converter::{Aggregation, Converter, Symbol};
+/// use rust_decimal::Decimal;
+/// use std::str::FromStr;
+/// use std::time::Duration;
+///
+/// let converter = Converter::builder()
+///     .exchangeratesapi()
+///     .fixer("<access key>")
+///     .aggregation(Aggregation::Median)
+///     .timeout(Duration::from_secs(5))
+///     .build()?;
+/// let converted = converter
+///     .convert(Decimal::new(100, 0), Symbol::from_str("EUR")?, Symbol::from_str("USD")?)
+///     .await?;
+/// println!("{:?}", converted.result()?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct ConverterBuilder {
+    sources: Vec<Source>,
+    aggregator: Aggregator,
+    cache: Option<(Arc<Mutex<RateCache>>, CacheConfig)>,
+    session: Option<Arc<Mutex<SessionRates>>>,
+    offline: bool,
+    network: NetworkConfig,
+    timeout: Option<Duration>,
+    retries: Option<u32>,
+    deadline: Option<Duration>,
+    quorum: Option<usize>,
+    progress: bool,
+}
+
+impl ConverterBuilder {
+    /// Fetches rates from exchangeratesapi.io, which needs no access key.
+    pub fn exchangeratesapi(mut self) -> Self {
+        self.sources.push(Source::ExchangeRatesApi);
+        self
+    }
+
+    pub fn fixer(mut self, access_key: impl Into<String>) -> Self {
+        self.sources.push(Source::Fixer(access_key.into()));
+        self
+    }
+
+    pub fn coinmarketcap(mut self, access_key: impl Into<String>) -> Self {
+        self.sources.push(Source::CoinMarketCap(access_key.into()));
+        self
+    }
+
+    /// Fetches rates from a provider of your own. Its requests are not subject to the
+    /// network settings of the builder.
+    pub fn provider(mut self, provider: Box<dyn Provider>) -> Self {
+        self.sources.push(Source::Custom(provider));
+        self
+    }
+
+    /// How the rates of several routes are combined, along with the safeguards the
+    /// result has to pass.
+    pub fn aggregator(mut self, aggregator: Aggregator) -> Self {
+        self.aggregator = aggregator;
+        self
+    }
+
+    pub fn aggregation(mut self, aggregation: Aggregation) -> Self {
+        self.aggregator.aggregation = aggregation;
+        self
+    }
+
+    /// Serves rates from `cache` while they are younger than configured and caches all
+    /// fetched rates. The cache is not saved, see `RateCache::save`.
+    pub fn cache(mut self, cache: Arc<Mutex<RateCache>>, config: CacheConfig) -> Self {
+        self.cache = Some((cache, config));
+        self
+    }
+
+    /// Reuses the rates of `session` regardless of their age and keeps all fetched
+    /// rates there, e.g. for the queries of an interactive session.
+    pub fn session(mut self, session: Arc<Mutex<SessionRates>>) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Answers from the cache alone, without touching the network.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Proxy, TLS settings and defaults of timeouts, retries and the deadline.
+    pub fn network(mut self, network: NetworkConfig) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Time a single request of any provider may take.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retries of requests failing transiently.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Time after which pending requests are given up and partial results used.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Number of succeeded requests after which pending ones are dropped.
+    pub fn quorum(mut self, quorum: usize) -> Self {
+        self.quorum = Some(quorum);
+        self
+    }
+
+    /// Renders the progress of requests on stderr if it is a terminal.
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn build(self) -> Result<Converter, anyhow::Error> {
+        let ConverterBuilder {
+            sources,
+            aggregator,
+            cache,
+            session,
+            offline,
+            network,
+            timeout,
+            retries,
+            deadline,
+            quorum,
+            progress,
+        } = self;
+
+        match (quorum, aggregator.min_providers) {
+            (Some(0), _) => return Err(anyhow!("Quorum must be a positive number")),
+            (Some(quorum), Some(min_providers)) if quorum < min_providers => {
+                return Err(anyhow!(
+                    "A quorum of {} routes cannot satisfy a minimum of {} providers",
+                    quorum,
+                    min_providers
+                ))
+            }
+            _ => {}
+        }
+        match (offline, &cache) {
+            (true, None) => return Err(anyhow!("Converting offline requires a cache")),
+            (false, _) if sources.is_empty() => return Err(anyhow!("No provider registered")),
+            _ => {}
+        }
+
+        let client = build_client(&network)?;
+        let http = |name: &str| {
+            let timeout = timeout.unwrap_or_else(|| network.timeout(name));
+            Http::new(client.clone(), timeout, retries.unwrap_or(network.retries))
+        };
+        let providers = sources
+            .into_iter()
+            .map(|source| {
+                let provider: Box<dyn Provider> = match source {
+                    Source::ExchangeRatesApi => {
+                        Box::new(ExchangeRatesApiProvider::new(http(exchangeratesapi::NAME)))
+                    }
+                    Source::Fixer(access_key) => {
+                        Box::new(FixerProvider::new(access_key, http(fixer::NAME)))
+                    }
+                    Source::CoinMarketCap(access_key) => Box::new(CoinMarketCapProvider::new(
+                        access_key,
+                        http(coinmarketcap::NAME),
+                    )),
+                    Source::Custom(provider) => provider,
+                };
+                let provider: Box<dyn Provider> = match &cache {
+                    Some((cache, config)) => {
+                        let max_age = config.max_age(&provider.get_name());
+                        Box::new(CachedProvider::new(provider, cache.clone(), max_age))
+                    }
+                    None => provider,
+                };
+                match &session {
+                    Some(session) => Box::new(SessionProvider::new(
+                        provider,
+                        session.clone(),
+                        cache.as_ref().map(|(cache, _)| cache.clone()),
+                    )),
+                    None => provider,
+                }
+            })
+            .collect();
+
+        Ok(Converter {
+            providers,
+            aggregator,
+            cache: cache.map(|(cache, _)| cache),
+            session,
+            offline,
+            wait: Wait {
+                deadline: deadline.or_else(|| network.deadline()),
+                quorum,
+            },
+            progress,
+        })
+    }
+}
+
+/// Converts amounts along all shortest routes between its providers and combines the
+/// rates of the routes into one. Built by `Converter::builder`.
+pub struct Converter {
+    providers: Vec<Box<dyn Provider>>,
+    aggregator: Aggregator,
+    cache: Option<Arc<Mutex<RateCache>>>,
+    session: Option<Arc<Mutex<SessionRates>>>,
+    offline: bool,
+    wait: Wait,
+    progress: bool,
+}
+
+/// Rate of one route of a conversion.
+#[derive(Debug)]
+pub struct RouteRate {
+    /// Like `TRY -(exchangeratesapi.io)-> USD`
+    pub route: String,
+    pub providers: Vec<String>,
+    pub rate: Result<Decimal, anyhow::Error>,
+    /// Why the rate did not count towards the result, e.g. as an outlier
+    pub discarded: Option<String>,
+    /// Age of the oldest rate of the route if it was served from the cache
+    pub cached_age: Option<Duration>,
+}
+
+/// An amount converted into one currency, along with the rates of all routes.
+#[derive(Debug)]
+pub struct Converted {
+    pub amount: Decimal,
+    pub base: Symbol,
+    pub quote: Symbol,
+    pub date: Option<NaiveDate>,
+    /// Rates of the successful routes combined by `aggregation`. Missing if no route
+    /// succeeded.
+    pub rate: Option<Decimal>,
+    pub aggregation: Aggregation,
+    /// Whether the rates were taken from the cache alone
+    pub offline: bool,
+    pub routes: Vec<RouteRate>,
+}
+
+impl Converted {
+    /// Fails with the error of the first failed route if no route returned a rate.
+    pub fn require_rate(self) -> Result<Converted, anyhow::Error> {
+        if self.rate.is_some() {
+            return Ok(self);
+        }
+        let error = self.routes.into_iter().find_map(|r| r.rate.err());
+        Err(match error {
+            Some(e) => e.context("No route returned a rate"),
+            None => anyhow!("No route returned a rate"),
+        })
+    }
+
+    /// The converted amount, unrounded. Fails if it is too large to be represented.
+    pub fn result(&self) -> Result<Option<Decimal>, anyhow::Error> {
+        self.rate
+            .map(|rate| {
+                self.amount.checked_mul(rate).with_context(|| {
+                    format!(
+                        "Arithmetic overflow converting {} {} to {}",
+                        self.amount, self.base, self.quote
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Rates of all successful routes.
+    pub fn rates(&self) -> Vec<Decimal> {
+        self.routes
+            .iter()
+            .filter_map(|r| r.rate.as_ref().ok())
+            .cloned()
+            .collect()
+    }
+
+    /// The conversion as printed by `--output json`, with results rounded by `round`.
+    pub fn report(
+        &self,
+        round: impl Fn(Decimal) -> Decimal,
+        now: DateTime<Utc>,
+    ) -> Result<ConversionReport, anyhow::Error> {
+        let rates = self.rates();
+
+        Ok(ConversionReport {
+            amount: self.amount,
+            base: self.base,
+            quote: self.quote,
+            date: self.date,
+            result: self.result()?.map(round),
+            rate: self.rate,
+            aggregation: self.aggregation,
+            std_deviation: rates.std_deviation(),
+            sample_std_deviation: rates.sample_std_deviation(),
+            coefficient_of_variation: rates.coefficient_of_variation(),
+            range: rates.range().map(|r| r.normalize()),
+            spread_bps: rates.spread_bps().map(|s| s.round_dp(2)),
+            offline: self.offline,
+            timestamp: now,
+            routes: self
+                .routes
+                .iter()
+                .map(|r| RouteReport {
+                    route: r.route.clone(),
+                    providers: r.providers.clone(),
+                    status: Status::of(&r.rate),
+                    rate: r.rate.as_ref().ok().cloned(),
+                    error: r.rate.as_ref().err().map(|e| format!("{:#}", e)),
+                    discarded: r.discarded.clone(),
+                    fetched_at: r.rate.as_ref().ok().map(|_| {
+                        let age = r.cached_age.unwrap_or_default();
+                        now - chrono::Duration::from_std(age)
+                            .unwrap_or_else(|_| chrono::Duration::zero())
+                    }),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// An expression whose amounts were converted into a single currency. Built by
+/// `Converter::evaluate`.
+#[derive(Debug)]
+pub struct Evaluated {
+    pub expression: Expr,
+    pub quote: Symbol,
+    pub date: Option<NaiveDate>,
+    pub total: Decimal,
+    /// Conversions of one unit of every currency of the expression other than `quote`
+    pub conversions: Vec<Converted>,
+    /// Whether the rates were taken from the cache alone
+    pub offline: bool,
+}
+
+impl Evaluated {
+    /// Rate of an amount in `base`. Amounts already in the target currency are taken as
+    /// they are.
+    pub fn rate(&self, base: Symbol) -> Decimal {
+        self.conversions
+            .iter()
+            .find(|c| c.base == base)
+            .and_then(|c| c.rate)
+            .unwrap_or_else(|| Decimal::new(1, 0))
+    }
+
+    /// The expression as printed by `--output json`, with results rounded by `round`.
+    pub fn report(
+        &self,
+        round: impl Fn(Decimal) -> Decimal,
+        now: DateTime<Utc>,
+    ) -> Result<ExpressionReport, anyhow::Error> {
+        let terms = self
+            .expression
+            .amounts()
+            .into_iter()
+            .map(|(amount, base)| {
+                let rate = self.rate(base);
+                let result = amount.checked_mul(rate).with_context(|| {
+                    format!("Arithmetic overflow converting {} {}", amount, base)
+                })?;
+                Ok(TermReport {
+                    amount,
+                    base,
+                    result: round(result),
+                    rate,
+                })
+            })
+            .collect::<Result<_, anyhow::Error>>()?;
+
+        Ok(ExpressionReport {
+            expression: self.expression.to_string(),
+            quote: self.quote,
+            date: self.date,
+            result: round(self.total),
+            terms,
+            conversions: self
+                .conversions
+                .iter()
+                .map(|c| c.report(&round, now))
+                .collect::<Result<_, _>>()?,
+            offline: self.offline,
+            timestamp: now,
+        })
+    }
+}
+
+/// Daily rates of a pair, merged from the series of several providers. Built by
+/// `Converter::series`.
+#[derive(Debug)]
+pub struct FetchedSeries {
+    pub pair: SymbolPair,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Mean rate of every day any provider returned a rate of
+    pub rates: Series,
+    /// Series of every provider serving the pair and the range, or why it failed
+    pub providers: Vec<(String, Result<Series, anyhow::Error>)>,
+}
+
+impl Converter {
+    pub fn builder() -> ConverterBuilder {
+        ConverterBuilder::default()
+    }
+
+    /// Registered providers, wrapped to use the cache if there is one.
+    pub fn providers(&self) -> &[Box<dyn Provider>] {
+        &self.providers
+    }
+
+    /// Offline, the cache alone serves rates of `date`. `None` if online.
+    pub fn offline_providers(&self, date: Option<NaiveDate>) -> Option<Vec<Box<dyn Provider>>> {
+        match (self.offline, &self.cache) {
+            (true, Some(cache)) => Some(vec![Box::new(OfflineProvider::new(cache.clone(), date))]),
+            _ => None,
+        }
+    }
+
+    pub fn aggregator(&self) -> &Aggregator {
+        &self.aggregator
+    }
+
+    pub fn cache(&self) -> Option<&Arc<Mutex<RateCache>>> {
+        self.cache.as_ref()
+    }
+
+    /// Rates kept for the session, if the converter was built with one.
+    pub fn session(&self) -> Option<&Arc<Mutex<SessionRates>>> {
+        self.session.as_ref()
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Converts `amount` with the latest rates.
+    pub async fn convert(
+        &self,
+        amount: Decimal,
+        base: Symbol,
+        quote: Symbol,
+    ) -> Result<Converted, anyhow::Error> {
+        self.convert_all(amount, base, &[quote], None)
+            .await?
+            .remove(0)
+    }
+
+    /// Converts `amount` with the rates of `date`.
+    pub async fn convert_on(
+        &self,
+        amount: Decimal,
+        base: Symbol,
+        quote: Symbol,
+        date: NaiveDate,
+    ) -> Result<Converted, anyhow::Error> {
+        self.convert_all(amount, base, &[quote], Some(date))
+            .await?
+            .remove(0)
+    }
+
+    /// Converts `amount` into each of `quotes`, fetching rates shared by their routes
+    /// once. Quotes without any route fail on their own, unless all do. Quotes whose
+    /// routes all failed are returned without a rate, along with the errors of their
+    /// routes. Fails as a whole if a result does not pass the safeguards of the
+    /// aggregator.
+    pub async fn convert_all(
+        &self,
+        amount: Decimal,
+        base: Symbol,
+        quotes: &[Symbol],
+        date: Option<NaiveDate>,
+    ) -> Result<Vec<Result<Converted, anyhow::Error>>, anyhow::Error> {
+        let offline = self.offline_providers(date);
+        let providers = offline.as_deref().unwrap_or(&self.providers);
+
+        // Providers without rates of the requested date are left out
+        let serves = |p: &dyn Provider| date.map(|d| p.supports_date(&d).is_ok()).unwrap_or(true);
+        if let Some(date) = date {
+            if !providers.iter().any(|p| serves(p.as_ref())) {
+                return Err(anyhow!("No provider serves rates of {}", date));
+            }
+        }
+
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().clear_hits();
+        }
+        let graph = Graph::with_filter(providers, &currency::symbols(), serves);
+        let mut routes = quotes
+            .iter()
+            .map(|quote| {
+                let routes = graph.find_routes(base, *quote, MAX_LEGS);
+                match (routes.is_empty(), self.offline) {
+                    (false, _) => Ok(routes),
+                    (true, true) => Err(anyhow!(
+                        "No cached rates to convert from {} to {}. Run once without --offline.",
+                        base,
+                        quote
+                    )),
+                    (true, _) => Err(anyhow!(
+                        "No provider route found from {} to {}",
+                        base,
+                        quote
+                    )),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Target currencies without routes fail on their own unless there is no other
+        if !routes.is_empty() && routes.iter().all(|r| r.is_err()) {
+            return Err(routes.remove(0).unwrap_err());
+        }
+
+        let leg_rates = fetch_legs(
+            &routes
+                .iter()
+                .filter_map(|r| r.as_ref().ok())
+                .map(|r| (date, r.as_slice()))
+                .collect::<Vec<_>>(),
+            providers,
+            self.progress,
+            self.wait,
+        )
+        .await
+        .remove(&date)
+        .unwrap_or_default();
+
+        // Rates of the routes of each quote, in the order of the routes
+        let conversions = routes
+            .into_iter()
+            .map(|routes| {
+                routes.map(|routes| {
+                    let results = routes
+                        .iter()
+                        .map(|r| r.rate_from(&leg_rates))
+                        .collect::<Vec<_>>();
+                    let aggregate = self
+                        .aggregator
+                        .aggregate_routes(&routes, &results, providers);
+                    (routes, results, aggregate)
+                })
+            })
+            .collect::<Vec<_>>();
+        for (quote, conversion) in quotes.iter().zip(conversions.iter()) {
+            // Quotes whose routes all failed are based on no provider at all
+            if let Ok((routes, results, aggregate)) = conversion {
+                self.aggregator
+                    .check(routes, results, aggregate.as_ref(), providers)
+                    .map_err(|e| e.context(format!("Refusing to convert {} to {}", base, quote)))?;
+            }
+        }
+
+        let cache = self.cache.as_ref().map(|c| c.lock().unwrap());
+        // A route is as old as its oldest cached leg
+        let cached_age = |route: &Route| {
+            let cache = cache.as_ref()?;
+            route
+                .legs
+                .iter()
+                .filter_map(|l| match self.offline {
+                    true => cache.latest(l.base, l.quote, date).map(|(_, age)| age),
+                    _ => cache.served(&providers[l.provider].get_name(), l.base, l.quote, date),
+                })
+                .max()
+        };
+
+        Ok(quotes
+            .iter()
+            .zip(conversions)
+            .map(|(quote, conversion)| {
+                let (routes, results, aggregate) = conversion?;
+                let mut discarded = aggregate
+                    .as_ref()
+                    .map(|a| a.discarded.clone())
+                    .unwrap_or_default();
+
+                Ok(Converted {
+                    amount,
+                    base,
+                    quote: *quote,
+                    date,
+                    rate: aggregate.map(|a| a.rate),
+                    aggregation: self.aggregator.aggregation,
+                    offline: self.offline,
+                    routes: routes
+                        .iter()
+                        .zip(results)
+                        .enumerate()
+                        .map(|(i, (route, rate))| RouteRate {
+                            route: route.describe(providers).to_string(),
+                            providers: route
+                                .legs
+                                .iter()
+                                .map(|l| providers[l.provider].get_name())
+                                .collect(),
+                            discarded: discarded.remove(&i),
+                            cached_age: cached_age(route),
+                            rate,
+                        })
+                        .collect(),
+                })
+            })
+            .collect())
+    }
+
+    /// Fetches the aggregated rate of each pair on its date, e.g. for the lines of a
+    /// batch. Dated pairs are only fetched along routes whose providers serve that date.
+    /// Legs shared by several pairs are fetched once, and the requests of all pairs are
+    /// grouped like those of a single conversion.
+    pub async fn convert_many(
+        &self,
+        keys: &[(SymbolPair, Option<NaiveDate>)],
+    ) -> HashMap<(SymbolPair, Option<NaiveDate>), Result<Decimal, anyhow::Error>> {
+        if !self.offline {
+            return fetch_rates(
+                &self.providers,
+                keys,
+                &self.aggregator,
+                self.wait,
+                self.progress,
+            )
+            .await;
+        }
+
+        // Offline providers serve a single date, so pairs are fetched by date
+        let dates = keys.iter().map(|(_, date)| *date).collect::<BTreeSet<_>>();
+        let mut rates = HashMap::new();
+        for date in dates {
+            let providers = self.offline_providers(date).unwrap_or_default();
+            let group = keys
+                .iter()
+                .filter(|(_, d)| *d == date)
+                .cloned()
+                .collect::<Vec<_>>();
+            let fetched = fetch_rates(&providers, &group, &self.aggregator, Wait::default(), false);
+            rates.extend(fetched.await);
+        }
+
+        rates
+    }
+
+    /// Fetches the daily rates of a pair from every provider with a time series of it
+    /// and merges them. Providers without rates of `from` or `to` are left out, and
+    /// series still pending after the deadline or once the quorum succeeded are dropped.
+    pub async fn series(
+        &self,
+        pair: SymbolPair,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<FetchedSeries, anyhow::Error> {
+        let mut providers = self.providers.iter().collect::<Vec<_>>();
+        for date in [from, to].iter() {
+            providers.retain(|p| p.supports_date(date).is_ok());
+            if providers.is_empty() {
+                return Err(anyhow!("No provider serves rates of {}", date));
+            }
+        }
+        providers.retain(|p| p.supports_pair(&pair.base, &pair.quote));
+        if providers.is_empty() {
+            return Err(anyhow!(
+                "No provider serves rates from {} to {} directly",
+                pair.base,
+                pair.quote
+            ));
+        }
+
+        let labels = providers.iter().map(|p| p.get_name()).collect();
+        let futures = providers
+            .iter()
+            .map(|p| {
+                with_deadline(
+                    self.wait.deadline,
+                    p.get_series(pair.base, pair.quote, from, to),
+                )
+            })
+            .collect::<Vec<_>>();
+        let results: Vec<Result<Series, anyhow::Error>> = join_quorum(
+            futures,
+            self.wait.quorum,
+            Progress::new(labels, self.progress),
+        )
+        .await
+        .into_iter()
+        .map(|r| r.unwrap_or_else(|| Err(anyhow!(Dropped))))
+        .collect();
+        let fetched = results
+            .iter()
+            .filter_map(|r| r.as_ref().ok())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        Ok(FetchedSeries {
+            pair,
+            from,
+            to,
+            rates: merge(&fetched),
+            providers: providers
+                .iter()
+                .map(|p| p.get_name())
+                .zip(results)
+                .collect(),
+        })
+    }
+
+    /// Converts every currency of `expression` into `quote` along all routes, like
+    /// single conversions, and computes the total. Fails if a currency gets no rate.
+    pub async fn evaluate(
+        &self,
+        expression: &Expr,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Evaluated, anyhow::Error> {
+        let mut bases = expression
+            .amounts()
+            .into_iter()
+            .map(|(_, base)| base)
+            .filter(|base| *base != quote)
+            .collect::<Vec<_>>();
+        bases.sort();
+        bases.dedup();
+
+        // Currencies are converted one after another so that their progress is not mixed up
+        let mut conversions = Vec::new();
+        for base in bases {
+            let converted = self
+                .convert_all(Decimal::new(1, 0), base, &[quote], date)
+                .await
+                .and_then(|mut converted| converted.remove(0))
+                .and_then(Converted::require_rate)
+                .with_context(|| format!("Failed to convert {} to {}", base, quote))?;
+            conversions.push(converted);
+        }
+
+        let mut evaluated = Evaluated {
+            expression: expression.clone(),
+            quote,
+            date,
+            total: Decimal::new(0, 0),
+            conversions,
+            offline: self.offline,
+        };
+        evaluated.total = expression.evaluate(&|base| Ok(evaluated.rate(base)))?;
+
+        Ok(evaluated)
+    }
+}
+
+/// Fetches the aggregated rate of every pair from `providers`. Routes of dated pairs are
+/// found among the providers serving that date.
+async fn fetch_rates(
+    providers: &[Box<dyn Provider>],
+    keys: &[(SymbolPair, Option<NaiveDate>)],
+    aggregator: &Aggregator,
+    wait: Wait,
+    progress: bool,
+) -> HashMap<(SymbolPair, Option<NaiveDate>), Result<Decimal, anyhow::Error>> {
+    let dates = keys.iter().map(|(_, date)| *date).collect::<BTreeSet<_>>();
+    let graphs = dates
+        .into_iter()
+        .map(|date| {
+            let serves =
+                move |p: &dyn Provider| date.map(|d| p.supports_date(&d).is_ok()).unwrap_or(true);
+            (
+                date,
+                Graph::with_filter(providers, &currency::symbols(), serves),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    let routes = keys
+        .iter()
+        .map(|(pair, date)| match pair.base == pair.quote {
+            // Batches often contain lines already in the target currency
+            true => vec![],
+            _ => graphs[date].find_routes(pair.base, pair.quote, MAX_LEGS),
+        })
+        .collect::<Vec<_>>();
+    let leg_rates = fetch_legs(
+        &keys
+            .iter()
+            .zip(routes.iter())
+            .filter(|(_, routes)| !routes.is_empty())
+            .map(|((_, date), routes)| (*date, routes.as_slice()))
+            .collect::<Vec<_>>(),
+        providers,
+        progress,
+        wait,
+    )
+    .await;
+
+    keys.iter()
+        .zip(routes)
+        .map(|((pair, date), routes)| {
+            let rate = if pair.base == pair.quote {
+                Ok(Decimal::new(1, 0))
+            } else if routes.is_empty() {
+                Err(anyhow!(
+                    "No provider route found from {} to {}",
+                    pair.base,
+                    pair.quote
+                ))
+            } else {
+                let results = routes
+                    .iter()
+                    .map(|r| r.rate_from(&leg_rates[date]))
+                    .collect::<Vec<_>>();
+                match aggregator.aggregate_routes(&routes, &results, providers) {
+                    Some(aggregate) => aggregator
+                        .check(&routes, &results, Some(&aggregate), providers)
+                        .map(|_| aggregate.rate),
+                    None => Err(results
+                        .into_iter()
+                        .find_map(|r| r.err())
+                        .unwrap_or_else(|| anyhow!("No rates fetched"))),
+                }
+            };
+            ((*pair, *date), rate)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Converter;
+    use crate::aggregation::Aggregator;
+    use crate::currency::{Symbol, SymbolPair};
+    use crate::expression;
+    use crate::providers::error::ProviderError;
+    use crate::providers::provider::Provider;
+
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use chrono::NaiveDate;
+    use reqwest::Url;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    struct MockProvider {
+        name: &'static str,
+        symbols: Vec<Symbol>,
+        /// Fails all requests if missing
+        rate: Option<Decimal>,
+        historical: bool,
+    }
+
+    #[async_trait]
+    impl Provider for MockProvider {
+        fn get_name(&self) -> String {
+            String::from(self.name)
+        }
+        fn supports(&self, symbol: &Symbol) -> bool {
+            self.symbols.contains(symbol)
+        }
+        fn supports_date(&self, _date: &NaiveDate) -> Result<(), ProviderError> {
+            match self.historical {
+                true => Ok(()),
+                _ => Err(ProviderError::Unsupported(String::from("No history"))),
+            }
+        }
+        fn build_url(
+            &self,
+            _base: &Symbol,
+            _quote: &Symbol,
+            _date: Option<NaiveDate>,
+        ) -> Result<Url, ProviderError> {
+            Err(ProviderError::Other(anyhow!("not needed")))
+        }
+        fn parse_rate_from_response(
+            &self,
+            _quote: &Symbol,
+            _response: &str,
+        ) -> Result<Decimal, ProviderError> {
+            Err(ProviderError::Other(anyhow!("not needed")))
+        }
+        async fn get_rate(
+            &self,
+            _base: Symbol,
+            _quote: Symbol,
+            _date: Option<NaiveDate>,
+        ) -> Result<Decimal, ProviderError> {
+            self.rate
+                .ok_or_else(|| ProviderError::Unsupported(String::from("unavailable")))
+        }
+    }
+
+    fn sym(code: &str) -> Symbol {
+        Symbol::from_str(code).unwrap()
+    }
+
+    fn provider(name: &'static str, rate: Option<Decimal>) -> Box<dyn Provider> {
+        Box::new(MockProvider {
+            name,
+            symbols: vec![sym("EUR"), sym("USD")],
+            rate,
+            historical: false,
+        })
+    }
+
+    fn pair(base: &str, quote: &str) -> SymbolPair {
+        SymbolPair {
+            base: sym(base),
+            quote: sym(quote),
+        }
+    }
+
+    #[tokio::test]
+    async fn converts_with_aggregate_of_routes() {
+        let converter = Converter::builder()
+            .provider(provider("first", Some(Decimal::new(2, 0))))
+            .provider(provider("second", Some(Decimal::new(4, 0))))
+            .build()
+            .unwrap();
+
+        let converted = converter
+            .convert(Decimal::new(100, 0), sym("EUR"), sym("USD"))
+            .await
+            .unwrap();
+
+        assert_eq!(converted.rate, Some(Decimal::new(3, 0)));
+        assert_eq!(converted.result().unwrap(), Some(Decimal::new(300, 0)));
+        assert_eq!(converted.routes.len(), 2);
+        assert_eq!(converted.routes[0].providers, vec!["first"]);
+        assert_eq!(converted.rates().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn fails_results_too_large() {
+        let converter = Converter::builder()
+            .provider(provider("first", Some(Decimal::new(2, 0))))
+            .build()
+            .unwrap();
+
+        let converted = converter
+            .convert(Decimal::MAX, sym("EUR"), sym("USD"))
+            .await
+            .unwrap();
+
+        assert_eq!(converted.rate, Some(Decimal::new(2, 0)));
+        assert!(converted.result().is_err());
+        assert!(converted.report(|r| r, chrono::Utc::now()).is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_quotes_without_routes_on_their_own() {
+        let converter = Converter::builder()
+            .provider(provider("first", Some(Decimal::new(2, 0))))
+            .build()
+            .unwrap();
+
+        let converted = converter
+            .convert_all(
+                Decimal::new(1, 0),
+                sym("EUR"),
+                &[sym("USD"), sym("ETH")],
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert!(converted[0].is_ok());
+        assert!(converted[1].is_err());
+    }
+
+    #[tokio::test]
+    async fn keeps_failed_routes_without_successful_route() {
+        let converter = Converter::builder()
+            .provider(provider("first", None))
+            .build()
+            .unwrap();
+
+        let converted = converter
+            .convert(Decimal::new(1, 0), sym("EUR"), sym("USD"))
+            .await
+            .unwrap();
+
+        assert_eq!(converted.rate, None);
+        assert_eq!(converted.routes.len(), 1);
+        assert!(converted.routes[0].rate.is_err());
+
+        let report = converted.report(|r| r, chrono::Utc::now()).unwrap();
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json["result"].is_null());
+        assert_eq!(json["routes"][0]["status"], "failed");
+        assert!(json["routes"][0]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn checks_minimum_of_providers_of_failed_quotes() {
+        let usd = MockProvider {
+            name: "usd",
+            symbols: vec![sym("EUR"), sym("USD")],
+            rate: Some(Decimal::new(2, 0)),
+            historical: false,
+        };
+        let gbp = MockProvider {
+            name: "gbp",
+            symbols: vec![sym("EUR"), sym("GBP")],
+            rate: None,
+            historical: false,
+        };
+        let converter = Converter::builder()
+            .provider(Box::new(usd))
+            .provider(Box::new(gbp))
+            .aggregator(Aggregator {
+                min_providers: Some(1),
+                ..Aggregator::default()
+            })
+            .build()
+            .unwrap();
+
+        let error = converter
+            .convert_all(
+                Decimal::new(1, 0),
+                sym("EUR"),
+                &[sym("USD"), sym("GBP")],
+                None,
+            )
+            .await
+            .unwrap_err();
+
+        assert!(format!("{:#}", error).starts_with("Refusing to convert EUR to GBP"));
+    }
+
+    #[tokio::test]
+    async fn finds_longer_routes_on_dates_without_shortest_route() {
+        let mock = |name, symbols, historical| MockProvider {
+            name,
+            symbols,
+            rate: Some(Decimal::new(2, 0)),
+            historical,
+        };
+        let converter = Converter::builder()
+            .provider(Box::new(mock(
+                "crypto",
+                vec![sym("EUR"), sym("ETH")],
+                false,
+            )))
+            .provider(Box::new(mock("fiat", vec![sym("EUR"), sym("USD")], true)))
+            .provider(Box::new(mock(
+                "history",
+                vec![sym("USD"), sym("ETH")],
+                true,
+            )))
+            .build()
+            .unwrap();
+        let key = (pair("EUR", "ETH"), NaiveDate::from_ymd_opt(2020, 3, 15));
+
+        let rates = converter
+            .convert_many(&[key, (pair("EUR", "ETH"), None)])
+            .await;
+
+        assert_eq!(rates[&key].as_ref().ok(), Some(&Decimal::new(4, 0)));
+        assert_eq!(
+            rates[&(pair("EUR", "ETH"), None)].as_ref().ok(),
+            Some(&Decimal::new(2, 0))
+        );
+    }
+
+    #[tokio::test]
+    async fn evaluates_expressions_with_rate_of_each_currency() {
+        let converter = Converter::builder()
+            .provider(provider("first", Some(Decimal::new(2, 0))))
+            .build()
+            .unwrap();
+        let expression = expression::parse("3 eur + 4 usd").unwrap();
+
+        let evaluated = converter
+            .evaluate(&expression, sym("USD"), None)
+            .await
+            .unwrap();
+
+        assert_eq!(evaluated.total, Decimal::new(10, 0));
+        assert_eq!(evaluated.conversions.len(), 1);
+        assert_eq!(evaluated.rate(sym("USD")), Decimal::new(1, 0));
+
+        let report = evaluated.report(|r| r, chrono::Utc::now()).unwrap();
+        assert_eq!(report.terms[0].result, Decimal::new(6, 0));
+        assert_eq!(report.terms[1].result, Decimal::new(4, 0));
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let quorum = Converter::builder()
+            .provider(provider("first", None))
+            .quorum(0)
+            .build();
+        let offline = Converter::builder().offline(true).build();
+        let empty = Converter::builder().build();
+
+        assert!(quorum.is_err());
+        assert!(offline.is_err());
+        assert!(empty.is_err());
+    }
+}
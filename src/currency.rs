@@ -1,149 +1,479 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 
 use lazy_static::lazy_static;
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+
+use crate::config::{Config, CurrencyConfig};
+
+// ISO 4217 catalog, embedded at build time. One currency per line, see header for columns.
+const ISO_4217: &str = include_str!("../data/iso4217.csv");
+
+// Long enough for ISO codes as well as the tickers of most crypto currencies.
+const MAX_SYMBOL_LEN: usize = 12;
+
+// Fuzzy matching: a currency is only guessed from a misspelled input if the edit
+// distance is at most a quarter of the length of the matched name. Suggestions are
+// more lenient.
+const ACCEPT_DISTANCE_DIVISOR: usize = 4;
+const SUGGEST_DISTANCE_DIVISOR: usize = 2;
+const MAX_SUGGESTIONS: usize = 5;
+// Cost of each word of a name that is missing in the input, e.g. "dollar" vs. "us dollar"
+const MISSING_TOKEN_DISTANCE: usize = 2;
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Currency {
     pub symbol: Symbol,
+    numeric_code: Option<u16>,
+    minor_units: Option<u8>,
     sign: String,
     name: String,
     currency_type: CurrencyType,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Hash, Debug)]
-pub enum Symbol {
-    EUR,
-    USD,
-    GBP,
-    TL,
-    ETH,
-    BTC,
+/// Currency code like `EUR` or `ETH`. Codes are not restricted to known currencies so
+/// that any code returned by a provider can be represented.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Symbol {
+    code: [u8; MAX_SYMBOL_LEN],
+    len: u8,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub struct SymbolPair {
     pub base: Symbol,
     pub quote: Symbol,
 }
 
+/// A base currency converted into one or more quote currencies.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Conversion {
+    pub base: Symbol,
+    pub quotes: Vec<Symbol>,
+}
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        // Only ASCII alphanumerics are ever stored, see `from_str`
+        std::str::from_utf8(&self.code[..self.len as usize]).unwrap()
+    }
+}
+
+impl FromStr for Symbol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        if s.is_empty() || s.len() > MAX_SYMBOL_LEN {
+            return Err(anyhow!(
+                "Currency code {} must have between 1 and {} characters",
+                s,
+                MAX_SYMBOL_LEN
+            ));
+        }
+        if !s.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(anyhow!("Currency code {} must be alphanumeric", s));
+        }
+
+        let mut code = [0; MAX_SYMBOL_LEN];
+        code[..s.len()].copy_from_slice(s.to_ascii_uppercase().as_bytes());
+
+        Ok(Symbol {
+            code,
+            len: s.len() as u8,
+        })
+    }
+}
+
 impl fmt::Display for Symbol {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.symbol)
     }
 }
 
-#[derive(Clone, Debug, Copy, Eq, PartialEq, Hash)]
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Copy, Eq, PartialEq, Hash)]
+#[serde(rename_all = "lowercase")]
 pub enum CurrencyType {
     Fiat,
     Crypto,
 }
 
+impl Currency {
+    pub fn currency_type(&self) -> CurrencyType {
+        self.currency_type
+    }
+
+    fn parse_iso_4217(line: &str) -> Result<Self, anyhow::Error> {
+        let fields = line.split(',').collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(anyhow!("Expected 5 fields in {}", line));
+        }
+
+        Ok(Currency {
+            symbol: fields[0].parse()?,
+            numeric_code: Some(fields[1])
+                .filter(|f| !f.is_empty())
+                .map(u16::from_str)
+                .transpose()?,
+            minor_units: Some(fields[2])
+                .filter(|f| !f.is_empty())
+                .map(u8::from_str)
+                .transpose()?,
+            name: String::from(fields[3]),
+            sign: String::from(fields[4]),
+            currency_type: CurrencyType::Fiat,
+        })
+    }
+
+    fn from_config(config: &CurrencyConfig) -> Result<Self, anyhow::Error> {
+        Ok(Currency {
+            symbol: config
+                .code
+                .parse()
+                .with_context(|| format!("Invalid code of currency {}", config.name))?,
+            numeric_code: None,
+            minor_units: config.minor_units,
+            sign: config.sign.clone(),
+            name: config.name.clone(),
+            currency_type: config.currency_type,
+        })
+    }
+}
+
+#[derive(Clone)]
 pub struct Currencies {
-    pub currencies: HashSet<Arc<Currency>>,
-    guesses: HashMap<&'static str, Arc<Currency>>,
+    pub currencies: HashMap<Symbol, Arc<Currency>>,
+    guesses: HashMap<String, Symbol>,
 }
 
 impl Currencies {
     pub fn guess(&self, guess: &str) -> Option<&Currency> {
-        (self.guesses.get(guess)).map(|o| &**o)
+        self.guesses.get(guess).and_then(|s| self.get(s))
     }
-}
 
-// IMPROVE: No need for laziness. A `const` HashMap seems best so phf crate would be ideal. However, phf
-// only supports maps and sets and does currently not seem to support structs as `Currencies`.
-lazy_static! {
-    static ref CURRENCIES: Currencies = {
-        let mut currencies = HashSet::<Arc<Currency>>::new();
-        let mut guesses = HashMap::<&'static str, Arc<Currency>>::new();
-
-        let usd = Arc::new(Currency {
-            symbol: Symbol::USD,
-            sign: String::from("$"),
-            name: String::from("US Dollar"),
-            currency_type: CurrencyType::Fiat,
-        });
-        let eur = Arc::new(Currency {
-            symbol: Symbol::EUR,
-            sign: String::from("€"),
-            name: String::from("Euro"),
-            currency_type: CurrencyType::Fiat,
-        });
-        let gbp = Arc::new(Currency {
-            symbol: Symbol::GBP,
-            sign: String::from("£"),
-            name: String::from("British Pounds"),
-            currency_type: CurrencyType::Fiat,
-        });
-        let tl = Arc::new(Currency {
-            symbol: Symbol::TL,
-            sign: String::from("₺"),
-            name: String::from("Turkish Lira"),
-            currency_type: CurrencyType::Fiat,
-        });
-        let eth = Arc::new(Currency {
-            symbol: Symbol::ETH,
-            sign: String::from("Ξ"),
-            name: String::from("Ether"),
-            currency_type: CurrencyType::Crypto,
-        });
-
-        // fiat
-        guesses.insert("usd", usd.clone());
-        guesses.insert("usdollar", usd.clone());
-        guesses.insert("$", usd.clone());
-        guesses.insert("eur", eur.clone());
-        guesses.insert("euro", eur.clone());
-        guesses.insert("€", eur.clone());
-        guesses.insert("gbp", eur.clone());
-        guesses.insert("pound", gbp.clone());
-        guesses.insert("pounds", gbp.clone());
-        guesses.insert("£", gbp.clone());
-        guesses.insert("tl", tl.clone());
-        guesses.insert("₺", tl.clone());
-        guesses.insert("turkish lira", tl.clone());
-        // cyrpto
-        guesses.insert("eth", eth.clone());
-        guesses.insert("eths", eth.clone());
-        guesses.insert("ether", eth.clone());
-        guesses.insert("ethers", eth.clone());
-        guesses.insert("Ξ", eth.clone());
-        guesses.insert("ethereum", eth.clone());
-
-        currencies.insert(usd);
-        currencies.insert(eur);
-        currencies.insert(gbp);
-        currencies.insert(tl);
-        currencies.insert(eth);
+    pub fn get(&self, symbol: &Symbol) -> Option<&Currency> {
+        self.currencies.get(symbol).map(|o| &**o)
+    }
+
+    /// Adds the currencies and aliases of a user config. Fails without modifying
+    /// anything if an alias would change the meaning of an existing guess.
+    pub fn merge(&mut self, config: &Config) -> Result<(), anyhow::Error> {
+        let mut merged = self.clone();
+
+        for currency_config in config.currencies.iter() {
+            let currency = Currency::from_config(currency_config)?;
+            let symbol = currency.symbol;
+            merged.currencies.insert(symbol, Arc::new(currency));
+
+            merged.add_guess(symbol.as_str(), symbol)?;
+            merged.add_guess(&currency_config.name, symbol)?;
+            if !currency_config.sign.is_empty() {
+                merged.add_guess(&currency_config.sign, symbol)?;
+            }
+            for alias in currency_config.aliases.iter() {
+                merged.add_guess(alias, symbol)?;
+            }
+        }
+
+        // Sorted so that the first collision is reported deterministically
+        let mut aliases = config.aliases.iter().collect::<Vec<_>>();
+        aliases.sort();
+        for (alias, code) in aliases {
+            let symbol = merged.known_symbol(code, alias)?;
+            merged.add_guess(alias, symbol)?;
+        }
+        for (alias, code) in config.overrides.iter() {
+            let symbol = merged.known_symbol(code, alias)?;
+            merged.guesses.insert(alias.to_lowercase(), symbol);
+        }
+
+        *self = merged;
+        Ok(())
+    }
+
+    fn add_guess(&mut self, guess: &str, symbol: Symbol) -> Result<(), anyhow::Error> {
+        let guess = guess.to_lowercase();
+
+        match self.guesses.get(&guess) {
+            Some(existing) if *existing != symbol => Err(anyhow!(
+                "Alias \"{}\" for {} collides with the existing alias for {}. Use [overrides] to replace it",
+                guess,
+                symbol,
+                existing
+            )),
+            _ => {
+                self.guesses.insert(guess, symbol);
+                Ok(())
+            }
+        }
+    }
+
+    /// Ranks known currencies by how closely any of their names, codes or aliases
+    /// resembles `input`. Returns the symbols with their edit distance, best first.
+    pub fn suggest(&self, input: &str) -> Vec<(Symbol, usize)> {
+        let input = normalize(input);
+        let mut best = HashMap::<Symbol, usize>::new();
+
+        for (guess, symbol) in self.guesses.iter() {
+            let distance = fuzzy_distance(&input, guess);
+            if distance > (guess.chars().count() / SUGGEST_DISTANCE_DIVISOR).max(1) {
+                continue;
+            }
+
+            let entry = best.entry(*symbol).or_insert(distance);
+            *entry = distance.min(*entry);
+        }
+
+        let mut suggestions = best.into_iter().collect::<Vec<_>>();
+        suggestions.sort_by_key(|(symbol, distance)| (*distance, *symbol));
+        suggestions
+    }
+
+    /// Looks up `input` exactly and falls back to the best fuzzy match, as long as it
+    /// is close enough and unambiguous.
+    pub fn find(&self, input: &str) -> Result<&Currency, anyhow::Error> {
+        if let Some(currency) = self.guess(&normalize(input)) {
+            return Ok(currency);
+        }
+
+        let suggestions = self.suggest(input);
+        if let Some((symbol, distance)) = suggestions.first() {
+            let is_unambiguous = suggestions.get(1).map(|(_, d)| d > distance) != Some(false);
+            let is_close = self.guesses.iter().any(|(guess, s)| {
+                s == symbol
+                    && fuzzy_distance(&normalize(input), guess)
+                        <= guess.chars().count() / ACCEPT_DISTANCE_DIVISOR
+            });
+
+            if is_unambiguous && is_close {
+                return Ok(self.get(symbol).unwrap());
+            }
+        }
+
+        match suggestions.is_empty() {
+            true => Err(anyhow!("Could not parse {} into a currency", input)),
+            false => Err(anyhow!(
+                "Could not parse {} into a currency. Did you mean {}?",
+                input,
+                suggestions
+                    .iter()
+                    .take(MAX_SUGGESTIONS)
+                    .filter_map(|(s, _)| self.get(s))
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    fn known_symbol(&self, code: &str, alias: &str) -> Result<Symbol, anyhow::Error> {
+        code.parse()
+            .ok()
+            .filter(|s| self.currencies.contains_key(s))
+            .with_context(|| format!("Alias \"{}\" refers to unknown currency {}", alias, code))
+    }
+
+    fn builtin() -> Self {
+        let mut currencies = HashMap::<Symbol, Arc<Currency>>::new();
+        let mut guesses = HashMap::<String, Symbol>::new();
+
+        let fiat = ISO_4217
+            .lines()
+            .skip(1)
+            .map(Currency::parse_iso_4217)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Embedded ISO 4217 catalog is malformed");
+        let crypto = vec![
+            crypto("BTC", "Bitcoin", "₿", 8),
+            crypto("ETH", "Ether", "Ξ", 18),
+        ];
+
+        // Sorted so that ambiguous names resolve deterministically
+        let mut all = fiat
+            .into_iter()
+            .chain(crypto)
+            .map(Arc::new)
+            .collect::<Vec<_>>();
+        all.sort_by_key(|c| c.symbol);
+
+        // Signs like "$" or "kr" are shared by several currencies and only make for a
+        // guess if they are unambiguous.
+        let mut sign_counts = HashMap::<&str, usize>::new();
+        for currency in all.iter() {
+            *sign_counts.entry(&currency.sign).or_default() += 1;
+        }
+
+        for currency in all.iter() {
+            currencies.insert(currency.symbol, currency.clone());
+            guesses.insert(currency.symbol.as_str().to_lowercase(), currency.symbol);
+        }
+        for currency in all.iter() {
+            guesses
+                .entry(currency.name.to_lowercase())
+                .or_insert(currency.symbol);
+            if sign_counts.get(currency.sign.as_str()) == Some(&1) {
+                guesses
+                    .entry(currency.sign.to_lowercase())
+                    .or_insert(currency.symbol);
+            }
+        }
+
+        let aliases = vec![
+            // fiat
+            ("usdollar", "USD"),
+            ("$", "USD"),
+            ("pound", "GBP"),
+            ("pounds", "GBP"),
+            ("£", "GBP"),
+            ("¥", "JPY"),
+            ("tl", "TRY"),
+            // crypto
+            ("eths", "ETH"),
+            ("ethers", "ETH"),
+            ("ethereum", "ETH"),
+        ];
+        for (alias, code) in aliases {
+            guesses.insert(String::from(alias), Symbol::from_str(code).unwrap());
+        }
 
         Currencies {
             currencies,
             guesses,
         }
-    };
+    }
+}
+
+/// All symbols of known currencies, e.g. to serve as nodes of the routing graph.
+pub fn symbols() -> Vec<Symbol> {
+    let mut symbols = CURRENCIES
+        .read()
+        .unwrap()
+        .currencies
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    symbols.sort();
+    symbols
+}
+
+pub fn lookup(symbol: &Symbol) -> Option<Currency> {
+    CURRENCIES.read().unwrap().get(symbol).cloned()
+}
+
+/// Looks up a code, name, sign or alias as is, without guessing from similar input.
+pub fn find_exact(input: &str) -> Option<Currency> {
+    CURRENCIES.read().unwrap().guess(&normalize(input)).cloned()
+}
+
+/// Merges the currencies and aliases of the user config into the known currencies.
+pub fn register(config: &Config) -> Result<(), anyhow::Error> {
+    CURRENCIES.write().unwrap().merge(config)
+}
+
+fn crypto(code: &str, name: &str, sign: &str, minor_units: u8) -> Currency {
+    Currency {
+        symbol: code.parse().unwrap(),
+        numeric_code: None,
+        minor_units: Some(minor_units),
+        sign: String::from(sign),
+        name: String::from(name),
+        currency_type: CurrencyType::Crypto,
+    }
+}
+
+// IMPROVE: No need for laziness. A `const` HashMap seems best so phf crate would be ideal. However, phf
+// only supports maps and sets and does currently not seem to support structs as `Currencies`.
+lazy_static! {
+    static ref CURRENCIES: RwLock<Currencies> = RwLock::new(Currencies::builtin());
+}
+
+fn normalize(input: &str) -> String {
+    input
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
 }
 
-#[derive(Clone, Debug)]
-pub struct ParseCurrencyError(String);
+/// Edit distance of `input` to `candidate`, either as a whole or word by word so that
+/// word order and missing words are tolerated.
+fn fuzzy_distance(input: &str, candidate: &str) -> usize {
+    let input_tokens = input.split(' ').collect::<Vec<_>>();
+    let candidate_tokens = candidate.split(' ').collect::<Vec<_>>();
+
+    let token_distance = input_tokens
+        .iter()
+        .map(|i| {
+            candidate_tokens
+                .iter()
+                .map(|c| levenshtein(i, c))
+                .min()
+                .unwrap_or(0)
+        })
+        .sum::<usize>()
+        + candidate_tokens.len().saturating_sub(input_tokens.len()) * MISSING_TOKEN_DISTANCE;
+
+    levenshtein(input, candidate).min(token_distance)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
 
 impl FromStr for Currency {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, anyhow::Error> {
-        CURRENCIES
-            .guess(&s.to_lowercase())
-            .cloned()
-            .context(format!("Could not parse {} into a currency", s))
+        CURRENCIES.read().unwrap().find(s).cloned()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Currency;
+    use super::{levenshtein, lookup, symbols, Currencies, Currency, CurrencyType, Symbol};
+    use crate::config::Config;
     use std::str::FromStr;
 
     #[test]
@@ -171,4 +501,189 @@ mod tests {
             input
         );
     }
+
+    #[test]
+    fn parses_currency_from_iso_code_name_and_sign() {
+        let expected = Symbol::from_str("CHF").ok();
+
+        for input in &["chf", "CHF", "Swiss Franc", "swiss franc"] {
+            let symbol = Currency::from_str(input).ok().map(|c| c.symbol);
+
+            assert_eq!(symbol, expected, "{} should be parsed as CHF", input);
+        }
+    }
+
+    #[test]
+    fn does_not_guess_ambiguous_signs() {
+        let input = "kr";
+
+        let currency = Currency::from_str(input);
+
+        assert!(currency.is_err(), "Sign {} should be ambiguous", input);
+    }
+
+    #[test]
+    fn loads_iso_4217_catalog() {
+        let jpy = lookup(&Symbol::from_str("JPY").unwrap()).unwrap();
+
+        assert_eq!(jpy.numeric_code, Some(392));
+        assert_eq!(jpy.minor_units, Some(0));
+        assert_eq!(jpy.currency_type(), CurrencyType::Fiat);
+        assert!(symbols().len() > 150, "All ISO 4217 codes should be known");
+    }
+
+    #[test]
+    fn parses_symbol_case_insensitively() {
+        let symbol = Symbol::from_str("usd").ok();
+
+        assert_eq!(symbol, Symbol::from_str("USD").ok());
+        assert_eq!(symbol.map(|s| s.to_string()), Some(String::from("USD")));
+    }
+
+    #[test]
+    fn fails_parsing_invalid_symbol() {
+        assert!(Symbol::from_str("").is_err());
+        assert!(Symbol::from_str("US D").is_err());
+        assert!(Symbol::from_str("VERYLONGTICKER").is_err());
+    }
+
+    #[test]
+    fn deserializes_unknown_symbol() {
+        let symbol = serde_json::from_str::<Symbol>(r#""DOGE""#).ok();
+
+        assert_eq!(symbol, Symbol::from_str("DOGE").ok());
+    }
+
+    #[test]
+    fn merges_config_currencies_and_aliases() {
+        let config = r#"
+            [aliases]
+            lira = "TRY"
+
+            [overrides]
+            "$" = "CAD"
+
+            [[currencies]]
+            code = "ACME"
+            name = "Acme Token"
+            type = "crypto"
+            aliases = ["acme coin"]
+        "#
+        .parse::<Config>()
+        .unwrap();
+        let mut currencies = Currencies::builtin();
+
+        currencies.merge(&config).unwrap();
+
+        let guess = |s: &str| currencies.guess(s).map(|c| c.symbol.to_string());
+        assert_eq!(guess("lira"), Some(String::from("TRY")));
+        assert_eq!(guess("$"), Some(String::from("CAD")));
+        assert_eq!(guess("acme"), Some(String::from("ACME")));
+        assert_eq!(guess("acme token"), Some(String::from("ACME")));
+        assert_eq!(guess("acme coin"), Some(String::from("ACME")));
+    }
+
+    #[test]
+    fn overrides_builtin_currency() {
+        let config = r#"
+            [[currencies]]
+            code = "TRY"
+            name = "Turkish Lira"
+            sign = "TL"
+            minor_units = 4
+        "#
+        .parse::<Config>()
+        .unwrap();
+        let mut currencies = Currencies::builtin();
+
+        currencies.merge(&config).unwrap();
+
+        let currency = currencies.guess("tl").unwrap();
+        assert_eq!(currency.minor_units, Some(4));
+    }
+
+    #[test]
+    fn fails_merging_colliding_alias() {
+        let config = "[aliases]\nbuck = \"USD\"\neuro = \"USD\""
+            .parse::<Config>()
+            .unwrap();
+        let mut currencies = Currencies::builtin();
+
+        let result = currencies.merge(&config);
+
+        assert!(result.is_err(), "Alias euro should collide with EUR");
+        assert!(
+            currencies.guess("buck").is_none(),
+            "Failed merges should not modify the currencies"
+        );
+    }
+
+    #[test]
+    fn fails_merging_alias_of_unknown_currency() {
+        let config = "[aliases]\nbuck = \"XYZ\"".parse::<Config>().unwrap();
+        let mut currencies = Currencies::builtin();
+
+        let result = currencies.merge(&config);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn computes_levenshtein_distance() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("euro", "euros"), 1);
+        assert_eq!(levenshtein("turksh lira", "turkish lira"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn accepts_close_fuzzy_matches() {
+        for (input, expected) in &[
+            ("turksh lira", "TRY"),
+            ("euros", "EUR"),
+            ("US dollars", "USD"),
+            ("dollars  US", "USD"),
+            ("swiss  francs", "CHF"),
+        ] {
+            let symbol = Currency::from_str(input).ok().map(|c| c.symbol.to_string());
+
+            assert_eq!(
+                symbol.as_deref(),
+                Some(*expected),
+                "Fuzzy match of {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn suggests_candidates_for_ambiguous_input() {
+        let currencies = Currencies::builtin();
+
+        let error = currencies.find("dollar").unwrap_err().to_string();
+
+        assert!(error.contains("Did you mean"), "{}", error);
+        assert!(error.contains("Dollar ("), "{}", error);
+    }
+
+    #[test]
+    fn suggests_but_does_not_accept_distant_matches() {
+        let currencies = Currencies::builtin();
+
+        let error = currencies.find("usdd").unwrap_err().to_string();
+
+        assert!(error.contains("US Dollar (USD)"), "{}", error);
+    }
+
+    #[test]
+    fn ranks_suggestions_by_distance() {
+        let currencies = Currencies::builtin();
+
+        let suggestions = currencies.suggest("swiss frank");
+
+        assert_eq!(
+            suggestions.first().map(|(s, _)| s.to_string()),
+            Some(String::from("CHF"))
+        );
+    }
 }
@@ -0,0 +1,466 @@
+use anyhow::{anyhow, Context};
+use rust_decimal::Decimal;
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::amount::Amount;
+use crate::currency::{Currency, Symbol};
+
+const OPERATORS: &[char] = &['+', '-', '*', '/', '(', ')'];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Amount),
+    Word(String),
+    Operator(char),
+}
+
+/// Arithmetic over amounts of money like `120 usd + 35 eur - 10 gbp` or `3 * 19.99 eur`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(Decimal),
+    Amount(Decimal, Symbol),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Op {
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Add | Op::Sub => 1,
+            Op::Mul | Op::Div => 2,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+        };
+        write!(f, "{}", sign)
+    }
+}
+
+/// Result of an evaluated expression. Amounts are in the target currency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Number(Decimal),
+    Money(Decimal),
+}
+
+/// Whether the words of amounts contain arithmetic, an operator between two numbers,
+/// and need to be parsed as an expression rather than a plain amount. Words after the
+/// separator of the target currency, like a date, must be left out.
+pub fn is_expression(words: &[&str]) -> bool {
+    let tokens = match tokenize(&words.join(" ")) {
+        Ok(tokens) => tokens,
+        Err(_) => return false,
+    };
+    let is_number = |t: &Token| matches!(t, Token::Number(_));
+
+    tokens.iter().enumerate().any(|(i, token)| {
+        matches!(token, Token::Operator(c) if !matches!(c, '(' | ')'))
+            && tokens[..i].iter().any(is_number)
+            && tokens[i + 1..].iter().any(is_number)
+    })
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, anyhow::Error> {
+    let mut tokens = vec![];
+    let mut chunks = input
+        .split(|c: char| c.is_whitespace() || OPERATORS.contains(&c))
+        .peekable();
+    let mut operators = input.matches(|c: char| c.is_whitespace() || OPERATORS.contains(&c));
+
+    // Chunks between whitespace and operators are amounts like `$19.99` if they contain
+    // digits and words of currency names otherwise
+    while let Some(chunk) = chunks.next() {
+        if chunk.contains(|c: char| c.is_ascii_digit()) {
+            tokens.push(Token::Number(Amount::from_str(chunk)?));
+        } else if !chunk.is_empty() {
+            tokens.push(Token::Word(chunk.to_string()));
+        }
+        if chunks.peek().is_some() {
+            let c = operators.next().and_then(|o| o.chars().next());
+            if let Some(c) = c.filter(|c| OPERATORS.contains(c)) {
+                tokens.push(Token::Operator(c));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn operator(&mut self, operators: &[char]) -> Option<char> {
+        match self.peek() {
+            Some(Token::Operator(c)) if operators.contains(c) => {
+                let c = *c;
+                self.position += 1;
+                Some(c)
+            }
+            _ => None,
+        }
+    }
+
+    // expression := product (('+' | '-') product)*
+    fn expression(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut left = self.product()?;
+        while let Some(c) = self.operator(&['+', '-']) {
+            let op = if c == '+' { Op::Add } else { Op::Sub };
+            left = Expr::Binary(Box::new(left), op, Box::new(self.product()?));
+        }
+        Ok(left)
+    }
+
+    // product := unary (('*' | '/') unary)*
+    fn product(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut left = self.unary()?;
+        while let Some(c) = self.operator(&['*', '/']) {
+            let op = if c == '*' { Op::Mul } else { Op::Div };
+            left = Expr::Binary(Box::new(left), op, Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    // unary := ('-' | '+') unary | primary
+    fn unary(&mut self) -> Result<Expr, anyhow::Error> {
+        match self.operator(&['+', '-']) {
+            Some('-') => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(_) => self.unary(),
+            None => self.primary(),
+        }
+    }
+
+    // primary := number currency? | '(' expression ')'
+    fn primary(&mut self) -> Result<Expr, anyhow::Error> {
+        match self.next() {
+            Some(Token::Number(amount)) => {
+                let mut words = vec![];
+                while let Some(Token::Word(word)) = self.peek() {
+                    words.push(word.clone());
+                    self.position += 1;
+                }
+                let named = match words.is_empty() {
+                    true => None,
+                    _ => Some(Currency::from_str(&words.join(" "))?.symbol),
+                };
+                match (amount.currency, named) {
+                    (Some(sign), Some(named)) if sign != named => Err(anyhow!(
+                        "Amount is in {} but {} is given as its currency",
+                        sign,
+                        named
+                    )),
+                    (sign, named) => match sign.or(named) {
+                        Some(symbol) => Ok(Expr::Amount(amount.value, symbol)),
+                        None => Ok(Expr::Number(amount.value)),
+                    },
+                }
+            }
+            Some(Token::Operator('(')) => {
+                let inner = self.expression()?;
+                match self.next() {
+                    Some(Token::Operator(')')) => Ok(inner),
+                    _ => Err(anyhow!("Missing closing parenthesis")),
+                }
+            }
+            Some(Token::Word(word)) => Err(anyhow!("Expected an amount before '{}'", word)),
+            Some(Token::Operator(c)) => Err(anyhow!("Unexpected '{}'", c)),
+            None => Err(anyhow!("Unexpected end of expression")),
+        }
+    }
+}
+
+/// Parses an expression over amounts. Terms are numbers optionally followed by a
+/// currency, combined with `+`, `-`, `*`, `/` and parentheses.
+pub fn parse(input: &str) -> Result<Expr, anyhow::Error> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        position: 0,
+    };
+    let expression = parser.expression()?;
+
+    match parser.next() {
+        None => Ok(expression),
+        Some(Token::Operator(c)) => Err(anyhow!("Unexpected '{}'", c)),
+        Some(Token::Number(n)) => Err(anyhow!("Expected an operator before '{}'", n.value)),
+        Some(Token::Word(w)) => Err(anyhow!("Expected an operator before '{}'", w)),
+    }
+}
+
+impl Expr {
+    /// Amounts of money in the order they appear.
+    pub fn amounts(&self) -> Vec<(Decimal, Symbol)> {
+        match self {
+            Expr::Number(_) => vec![],
+            Expr::Amount(value, symbol) => vec![(*value, *symbol)],
+            Expr::Neg(inner) => inner.amounts(),
+            Expr::Binary(left, _, right) => {
+                let mut amounts = left.amounts();
+                amounts.extend(right.amounts());
+                amounts
+            }
+        }
+    }
+
+    /// Computes the total in the target currency, converting every amount with the rate
+    /// of its currency.
+    pub fn evaluate(
+        &self,
+        rate: &impl Fn(Symbol) -> Result<Decimal, anyhow::Error>,
+    ) -> Result<Decimal, anyhow::Error> {
+        match self.value(rate)? {
+            Value::Money(total) => Ok(total),
+            Value::Number(_) => Err(anyhow!("Expression has no currency, e.g. '3 * 19.99 eur'")),
+        }
+    }
+
+    fn value(
+        &self,
+        rate: &impl Fn(Symbol) -> Result<Decimal, anyhow::Error>,
+    ) -> Result<Value, anyhow::Error> {
+        let overflow = || anyhow!("Arithmetic overflow in '{}'", self);
+
+        match self {
+            Expr::Number(value) => Ok(Value::Number(*value)),
+            Expr::Amount(value, symbol) => value
+                .checked_mul(rate(*symbol)?)
+                .map(Value::Money)
+                .with_context(overflow),
+            Expr::Neg(inner) => Ok(match inner.value(rate)? {
+                Value::Number(value) => Value::Number(-value),
+                Value::Money(value) => Value::Money(-value),
+            }),
+            Expr::Binary(left, op, right) => {
+                use Value::*;
+
+                let (left, right) = (left.value(rate)?, right.value(rate)?);
+                let result = match (left, op, right) {
+                    (Number(a), Op::Add, Number(b)) => a.checked_add(b).map(Number),
+                    (Money(a), Op::Add, Money(b)) => a.checked_add(b).map(Money),
+                    (Number(a), Op::Sub, Number(b)) => a.checked_sub(b).map(Number),
+                    (Money(a), Op::Sub, Money(b)) => a.checked_sub(b).map(Money),
+                    (_, Op::Add, _) | (_, Op::Sub, _) => {
+                        return Err(anyhow!(
+                            "Cannot {} an amount and a plain number in '{}'",
+                            if *op == Op::Add { "add" } else { "subtract" },
+                            self
+                        ))
+                    }
+                    (Number(a), Op::Mul, Number(b)) => a.checked_mul(b).map(Number),
+                    (Money(a), Op::Mul, Number(b)) | (Number(a), Op::Mul, Money(b)) => {
+                        a.checked_mul(b).map(Money)
+                    }
+                    (Money(_), Op::Mul, Money(_)) => {
+                        return Err(anyhow!("Cannot multiply two amounts in '{}'", self))
+                    }
+                    (_, Op::Div, Number(b)) | (_, Op::Div, Money(b)) if b.is_zero() => {
+                        return Err(anyhow!("Division by zero in '{}'", self))
+                    }
+                    (Number(a), Op::Div, Number(b)) => a.checked_div(b).map(Number),
+                    (Money(a), Op::Div, Number(b)) => a.checked_div(b).map(Money),
+                    (_, Op::Div, Money(_)) => {
+                        return Err(anyhow!("Cannot divide by an amount in '{}'", self))
+                    }
+                };
+                result.with_context(overflow)
+            }
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(_, op, _) => op.precedence(),
+            _ => u8::MAX,
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Amount(value, symbol) => write!(f, "{} {}", value, symbol),
+            Expr::Neg(inner) => match inner.precedence() {
+                u8::MAX => write!(f, "-{}", inner),
+                _ => write!(f, "-({})", inner),
+            },
+            Expr::Binary(left, op, right) => {
+                // Right operands of the same precedence only need parentheses if the
+                // operator does not associate, like `a - (b - c)`
+                let (left_parens, right_parens) = (
+                    left.precedence() < op.precedence(),
+                    right.precedence() < op.precedence()
+                        || (right.precedence() == op.precedence()
+                            && matches!(op, Op::Sub | Op::Div)),
+                );
+                let wrap = |e: &Expr, parens: bool| match parens {
+                    true => format!("({})", e),
+                    _ => e.to_string(),
+                };
+                write!(
+                    f,
+                    "{} {} {}",
+                    wrap(left, left_parens),
+                    op,
+                    wrap(right, right_parens)
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_expression, parse};
+    use crate::currency::Symbol;
+
+    use anyhow::anyhow;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    fn symbol(code: &str) -> Symbol {
+        Symbol::from_str(code).unwrap()
+    }
+
+    // 1 USD = 10 TRY, 1 EUR = 12 TRY, 1 GBP = 14 TRY
+    fn rate(symbol: Symbol) -> Result<Decimal, anyhow::Error> {
+        match symbol.as_str() {
+            "USD" => Ok(Decimal::new(10, 0)),
+            "EUR" => Ok(Decimal::new(12, 0)),
+            "GBP" => Ok(Decimal::new(14, 0)),
+            _ => Err(anyhow!("No rate of {}", symbol)),
+        }
+    }
+
+    #[test]
+    fn detects_expressions() {
+        assert!(is_expression(&["120", "usd", "+", "35", "eur"]));
+        assert!(is_expression(&["3*19.99", "eur"]));
+        assert!(is_expression(&["-(1", "usd", "-", "3", "usd)", "*", "2"]));
+        assert!(!is_expression(&["100", "turkish", "lira"]));
+        assert!(!is_expression(&["-5", "usd"]));
+        assert!(!is_expression(&["100", "guinea-bissau", "peso"]));
+    }
+
+    #[test]
+    fn parses_sums_of_amounts() {
+        let expression = parse("120 usd + 35 eur - 10 gbp").unwrap();
+
+        assert_eq!(
+            expression.amounts(),
+            vec![
+                (Decimal::new(120, 0), symbol("USD")),
+                (Decimal::new(35, 0), symbol("EUR")),
+                (Decimal::new(10, 0), symbol("GBP")),
+            ]
+        );
+        assert_eq!(
+            expression.evaluate(&rate).unwrap(),
+            Decimal::new(120 * 10 + 35 * 12 - 10 * 14, 0)
+        );
+    }
+
+    #[test]
+    fn respects_precedence_and_parentheses() {
+        let product = parse("3*19.99eur").unwrap();
+        let symbol_product = parse("3*$19.99").unwrap();
+        let grouped = parse("(10 usd + 2 eur) / 2").unwrap();
+        let negated = parse("-(1 usd - 3 usd) * 2").unwrap();
+
+        assert_eq!(product.evaluate(&rate).unwrap(), Decimal::new(71964, 2));
+        assert_eq!(
+            symbol_product.evaluate(&rate).unwrap(),
+            Decimal::new(59970, 2)
+        );
+        assert_eq!(grouped.evaluate(&rate).unwrap(), Decimal::new(62, 0));
+        assert_eq!(negated.evaluate(&rate).unwrap(), Decimal::new(40, 0));
+    }
+
+    #[test]
+    fn parses_currency_names() {
+        let expression = parse("2 turkish lira + 1 us dollar").unwrap();
+
+        assert_eq!(
+            expression.amounts(),
+            vec![
+                (Decimal::new(2, 0), symbol("TRY")),
+                (Decimal::new(1, 0), symbol("USD"))
+            ]
+        );
+    }
+
+    #[test]
+    fn prints_minimal_parentheses() {
+        for input in &[
+            "1 USD - (2 EUR - 3 GBP)",
+            "(1 USD + 2 EUR) * 3",
+            "-(1 USD) * 2",
+        ] {
+            let printed = parse(input).unwrap().to_string();
+
+            assert_eq!(parse(&printed).unwrap(), parse(input).unwrap());
+        }
+        assert_eq!(
+            parse("(1 usd + 2 eur) - 3 gbp").unwrap().to_string(),
+            "1 USD + 2 EUR - 3 GBP"
+        );
+    }
+
+    #[test]
+    fn fails_on_mismatched_types() {
+        for input in &[
+            "1 usd + 2",
+            "1 usd * 2 eur",
+            "2 / 1 usd",
+            "3 * 4",
+            "1 usd / 0",
+        ] {
+            let result = parse(input).and_then(|e| e.evaluate(&rate));
+
+            assert!(result.is_err(), "{} should not evaluate", input);
+        }
+    }
+
+    #[test]
+    fn fails_on_malformed_input() {
+        for input in &["1 usd +", "(1 usd", "usd + 1 eur", "1 usd 2 eur", "1 usd )"] {
+            assert!(parse(input).is_err(), "{} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn fails_without_rate() {
+        let expression = parse("1 usd + 1 chf").unwrap();
+
+        assert!(expression.evaluate(&rate).is_err());
+    }
+}
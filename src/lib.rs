@@ -0,0 +1,27 @@
+//! Currency conversion with the rates of several providers, combined along all shortest
+//! routes between them. Start with `Converter::builder`.
+
+pub mod aggregation;
+pub mod amount;
+pub mod cache;
+pub mod config;
+pub mod converter;
+pub mod currency;
+pub mod expression;
+#[doc(hidden)]
+pub mod output;
+mod progress;
+mod progress_join;
+pub mod providers;
+mod routing;
+pub mod series;
+#[doc(hidden)]
+pub mod utils;
+
+pub use aggregation::{Aggregation, Aggregator};
+pub use cache::RateCache;
+pub use converter::{Converted, Converter, ConverterBuilder, Evaluated, FetchedSeries, RouteRate};
+pub use currency::{Currency, Symbol};
+pub use output::{ConversionReport, ExpressionReport};
+pub use providers::error::ProviderError;
+pub use providers::provider::Provider;
@@ -1,123 +1,367 @@
+mod batch;
 mod cli;
-mod currency;
-mod join_all_progress;
-mod providers;
-mod utils;
+mod convert;
+mod repl;
 
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
-use anyhow::*;
+use anyhow::{anyhow, Context};
+use chrono::{Local, NaiveDate, Utc};
 use colored::*;
 use rust_decimal::Decimal;
 
-use cli::{build_cli, parse_currencies};
-use join_all_progress::join_all_progress;
-use providers::coinmarketcap::CoinMarketCapProvider;
-use providers::exchangeratesapi::ExchangeRatesApiProvider;
-use providers::fixer::FixerProvider;
-use providers::provider::Provider;
-use utils::Stats;
+use currency_converter::cache::RateCache;
+use currency_converter::config::Config;
+use currency_converter::currency::{self, Currency, SymbolPair};
+use currency_converter::output::{BatchRow, DailyRate, ProviderReport, SeriesReport, Status};
+use currency_converter::providers::provider::Series;
+use currency_converter::series::{sparkline, Summary};
+use currency_converter::utils::Stats;
+use currency_converter::Converter;
+
+use cli::{build_cli, is_expression_query, parse_date, parse_expression_query, parse_query};
+use convert::{
+    build_converter, check_dates, convert, evaluate, print_guidance, print_usage, save_cache,
+    Options,
+};
 
 #[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("Error: {:?}", e);
+        print_guidance(&e);
+        process::exit(1);
+    }
+}
+
+async fn run() -> Result<(), anyhow::Error> {
     let matches = build_cli().get_matches();
 
-    let amount = matches
+    let config = Config::load(matches.value_of("config").map(Path::new))?;
+    currency::register(&config).context("Invalid currencies in config file")?;
+
+    let cache = Arc::new(Mutex::new(RateCache::load(
+        config.cache.path.clone().or_else(RateCache::default_path),
+    )));
+
+    let converter = build_converter(&matches, &config, cache.clone())?;
+    if let Some(path) = matches.value_of("batch") {
+        return convert_batch(&matches, &converter, path).await;
+    }
+    if matches.value_of("output") == Some("csv") {
+        return Err(anyhow!("CSV output is only supported with --batch"));
+    }
+    let options = Options::from_matches(&matches);
+    if matches.is_present("interactive") {
+        return repl::run(options, converter, cache).await;
+    }
+
+    let words = matches
         .value_of("amount")
-        .map(Decimal::from_str)
-        .unwrap()
-        .unwrap();
-    let symbols = parse_currencies(
-        matches
-            .values_of("currencies")
-            .context("No currency conversion found in input")?
-            .collect::<Vec<&str>>(),
-    )
-    .context("Failed to parse currency string")?;
-
-    if symbols.base == symbols.quote {
-        return Err(anyhow!("Input and output currency are identical."));
+        .into_iter()
+        .chain(
+            matches
+                .values_of("currencies")
+                .context("No currency conversion found in input")?,
+        )
+        .collect::<Vec<&str>>();
+
+    if is_expression_query(&words) {
+        if matches.is_present("from") {
+            return Err(anyhow!("Ranges cannot be shown for expressions"));
+        }
+        let mut query = parse_expression_query(words)?;
+        if let Some(input) = matches.value_of("date") {
+            query.date = Some(parse_date(input)?);
+        }
+        return evaluate(&options, &converter, &query).await;
     }
 
-    let mut providers: Vec<Box<dyn Provider>> = vec![Box::new(ExchangeRatesApiProvider::new())];
+    let mut query = parse_query(words)?;
+    if let Some(input) = matches.value_of("date") {
+        query.date = Some(parse_date(input)?);
+    }
+    if let Some(from) = matches.value_of("from") {
+        let from = parse_date(from)?;
+        let to = match matches.value_of("to") {
+            Some(to) => parse_date(to)?,
+            None => Local::now().date_naive(),
+        };
+        if from > to {
+            return Err(anyhow!("Range must not end before {}", from));
+        }
+        if query.date.is_some() {
+            return Err(anyhow!("A date cannot be combined with a range"));
+        }
+        let symbols = match query.conversion.quotes.as_slice() {
+            [quote] => SymbolPair {
+                base: query.conversion.base,
+                quote: *quote,
+            },
+            _ => {
+                return Err(anyhow!(
+                    "Ranges can only be shown for a single target currency"
+                ))
+            }
+        };
 
-    if let Some(access_key) = matches.value_of("access-key-fixer") {
-        providers.push(Box::new(FixerProvider::new(access_key.to_string())));
+        check_dates(converter.providers(), &[from, to])?;
+        return show_series(&options, &converter, symbols, query.amount, from, to).await;
     }
-    if let Some(access_key) = matches.value_of("access-key-coinmarketcap") {
-        providers.push(Box::new(CoinMarketCapProvider::new(access_key.to_string())));
+
+    convert(&options, &converter, &query).await
+}
+
+/// Converts every line of the batch input, fetching each distinct pair and date once,
+/// and prints one row per line.
+async fn convert_batch(
+    matches: &clap::ArgMatches,
+    converter: &Converter,
+    path: &str,
+) -> Result<(), anyhow::Error> {
+    let mut input = String::new();
+    match path {
+        "-" => io::stdin().read_to_string(&mut input),
+        _ => File::open(path).and_then(|mut f| f.read_to_string(&mut input)),
     }
+    .with_context(|| format!("Failed to read batch input {}", path))?;
+
+    let quote = matches
+        .value_of("into")
+        .map(|s| Currency::from_str(s).map(|c| c.symbol))
+        .transpose()
+        .context("Failed to parse target currency")?;
+    let entries = batch::parse_lines(&input, quote);
 
-    let futures = providers
+    let keys = entries
         .iter()
-        .map(|p| p.get_rate(symbols.base, symbols.quote))
+        .filter_map(|(_, e)| e.as_ref().ok())
+        .map(|e| e.key())
+        .collect::<HashSet<_>>()
+        .into_iter()
         .collect::<Vec<_>>();
+    let rates = converter.convert_many(&keys).await;
+    save_cache(converter);
 
-    // NOTE: must preserve order so we can associate future output with provider name
-    let rate_results: Vec<Option<Decimal>> = join_all_progress(futures)
-        .await
+    let round = |value: Decimal| match matches.is_present("precise") {
+        true => value.normalize(),
+        _ => value.round_dp(2).normalize(),
+    };
+    let rows = entries
         .into_iter()
-        .map(|r| r.ok())
-        .collect::<Vec<_>>();
-    let rates: Vec<Decimal> = rate_results
-        .iter()
-        .cloned()
-        .filter_map(|o| o)
+        .map(|(line, entry)| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    return BatchRow {
+                        line,
+                        amount: None,
+                        base: None,
+                        quote: None,
+                        date: None,
+                        result: None,
+                        rate: None,
+                        error: Some(format!("{:#}", e)),
+                    }
+                }
+            };
+            let rate = rates[&entry.key()]
+                .as_ref()
+                .map(|r| *r)
+                .map_err(|e| format!("{:#}", e));
+            let result = rate.clone().and_then(|rate| {
+                entry.amount.checked_mul(rate).ok_or_else(|| {
+                    format!(
+                        "Arithmetic overflow converting {} {}",
+                        entry.amount, entry.pair.base
+                    )
+                })
+            });
+
+            BatchRow {
+                line,
+                amount: Some(entry.amount),
+                base: Some(entry.pair.base),
+                quote: Some(entry.pair.quote),
+                date: entry.date,
+                result: result.as_ref().ok().map(|r| round(*r)),
+                rate: rate.ok(),
+                error: result.err(),
+            }
+        })
         .collect::<Vec<_>>();
 
-    let avg_rate = (&rates[..]).mean().context("No data to compute mean")?;
-    let quote_amount = amount * avg_rate;
+    let failed = rows.iter().filter(|r| r.error.is_some()).count();
+    if failed > 0 {
+        let message = format!("Failed to convert {}/{} lines", failed, rows.len());
+        eprintln!("{}", message.yellow());
+    }
+
+    match matches.value_of("output") {
+        Some("json") => println!("{}", serde_json::to_string_pretty(&rows)?),
+        _ => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            for row in rows.iter() {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+    }
 
-    let result = match matches.is_present("precise") {
-        true => quote_amount.normalize(),
-        _ => quote_amount.round_dp(2).normalize(),
+    Ok(())
+}
+
+/// Fetches the daily rates of the pair from every provider with a time series of it and
+/// prints their summary.
+async fn show_series(
+    options: &Options,
+    converter: &Converter,
+    symbols: SymbolPair,
+    amount: Decimal,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<(), anyhow::Error> {
+    let fetched = converter.series(symbols, from, to).await?;
+
+    let json = options.json;
+    let round = |rate: Decimal| match options.precise {
+        true => rate.normalize(),
+        _ => rate.round_dp(2).normalize(),
     };
+    let series = fetched
+        .rates
+        .iter()
+        .map(|(date, rate)| {
+            let converted = amount.checked_mul(*rate).with_context(|| {
+                format!("Arithmetic overflow converting {} on {}", amount, date)
+            })?;
+            Ok((*date, converted))
+        })
+        .collect::<anyhow::Result<Series>>()?;
+    let summary = Summary::of(&series).context("No rates fetched for this range")?;
+    let std_deviation = series.values().std_deviation();
 
-    match matches.is_present("raw") {
-        true => println!("{}", result),
-        _ => println!(
-            "{} {} ⟶  {} {}",
+    if json {
+        let daily = |(date, rate): (NaiveDate, Decimal)| DailyRate {
+            date,
+            rate: round(rate),
+        };
+        let report = SeriesReport {
             amount,
-            symbols.base.to_string().dimmed(),
-            result,
-            symbols.quote.to_string().dimmed()
-        ),
+            base: symbols.base,
+            quote: symbols.quote,
+            from,
+            to,
+            rates: series.iter().map(|(d, r)| daily((*d, *r))).collect(),
+            min: daily(summary.min),
+            max: daily(summary.max),
+            mean: round(summary.mean),
+            change: round(summary.change()),
+            change_percent: summary.change_percent().map(|p| p.round_dp(2)),
+            std_deviation,
+            coefficient_of_variation: series.values().coefficient_of_variation(),
+            timestamp: Utc::now(),
+            providers: fetched
+                .providers
+                .iter()
+                .map(|(provider, r)| ProviderReport {
+                    provider: provider.clone(),
+                    status: Status::of(r),
+                    days: r.as_ref().ok().map(|s| s.len()),
+                    error: r.as_ref().err().map(|e| format!("{:#}", e)),
+                })
+                .collect(),
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
     }
 
-    if matches.is_present("stats") {
-        let std_deviation = (&rates[..])
-            .std_deviation()
-            .map(|e| e.to_string().normal())
-            .unwrap_or_else(|| "<cannot compute>".italic());
-        let provider_statuses = providers
+    match options.raw {
+        true => series
             .iter()
-            .zip(rate_results)
-            .map(|(p, r)| {
-                if r.is_some() {
-                    p.get_name().green()
-                } else {
-                    p.get_name().dimmed()
+            .for_each(|(date, rate)| println!("{}\t{}", date, round(*rate))),
+        _ => {
+            let quote = symbols.quote.to_string();
+            let change_percent = summary
+                .change_percent()
+                .map(|p| format!(" ({:+}%)", p.round_dp(2)))
+                .unwrap_or_default();
+            let change = format!("{:+} {}{}", round(summary.change()), quote, change_percent);
+
+            println!(
+                "{} {} ⟶  {} from {} to {} ({} days)",
+                amount,
+                symbols.base.to_string().dimmed(),
+                quote.dimmed(),
+                from,
+                to,
+                series.len()
+            );
+            println!(
+                "{}",
+                sparkline(&series.values().cloned().collect::<Vec<_>>(), 60)
+            );
+            println!(
+                "Min:    {} {} on {}",
+                round(summary.min.1),
+                quote,
+                summary.min.0
+            );
+            println!(
+                "Max:    {} {} on {}",
+                round(summary.max.1),
+                quote,
+                summary.max.0
+            );
+            println!("Mean:   {} {}", round(summary.mean), quote);
+            println!(
+                "Change: {}",
+                match summary.change().is_sign_negative() {
+                    true => change.red(),
+                    _ => change.green(),
                 }
-            })
-            .fold(String::from(""), |mut acc, x| {
-                // Joins Vec<ColoredString>
-                acc.push_str(&x.to_string());
-                acc.push(' ');
-                acc
-            });
+            );
+        }
+    }
 
-        vec![
-            format!(
-                "Successfully fetched {}/{} sources: {}",
-                rates.len(),
-                providers.len(),
-                provider_statuses
-            ),
-            format!("Fetched rates: {:?}, σ: {}", rates, std_deviation),
-        ]
-        .iter()
-        .for_each(|l| println!("{}", l));
-    };
+    if options.stats {
+        let std_deviation = std_deviation
+            .map(|e| e.to_string().normal())
+            .unwrap_or_else(|| "<cannot compute>".italic());
+
+        println!(
+            "Successfully fetched {}/{} series:",
+            fetched.providers.iter().filter(|(_, r)| r.is_ok()).count(),
+            fetched.providers.len()
+        );
+        for (provider, result) in fetched.providers.iter() {
+            let status = match result {
+                Ok(s) => format!("  {}: {} days", provider, s.len()).green(),
+                Err(_) => match Status::of(result) {
+                    Status::TimedOut => format!("  {}: <timed out>", provider).yellow(),
+                    Status::Dropped => format!("  {}: <dropped>", provider).dimmed(),
+                    _ => format!("  {}: <failed>", provider).dimmed(),
+                },
+            };
+            println!("{}", status);
+        }
+        let coefficient_of_variation = series
+            .values()
+            .coefficient_of_variation()
+            .map(|c| c.round_dp(6).to_string().normal())
+            .unwrap_or_else(|| "<cannot compute>".italic());
+        println!(
+            "σ: {}, coefficient of variation: {}",
+            std_deviation, coefficient_of_variation
+        );
+        print_usage(converter.providers());
+    }
 
     Ok(())
 }
@@ -0,0 +1,208 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::aggregation::Aggregation;
+use crate::currency::Symbol;
+use crate::progress_join::Dropped;
+use crate::providers::http::is_timeout;
+
+pub const FORMATS: &[&str] = &["text", "json", "csv"];
+
+/// A conversion as printed by `--output json`.
+#[derive(Serialize, Debug)]
+pub struct ConversionReport {
+    pub amount: Decimal,
+    pub base: Symbol,
+    pub quote: Symbol,
+    pub date: Option<NaiveDate>,
+    /// Missing if no route succeeded
+    pub result: Option<Decimal>,
+    /// Rates of all successful routes combined by `aggregation`
+    pub rate: Option<Decimal>,
+    pub aggregation: Aggregation,
+    /// Spread of the rates of all successful routes
+    pub std_deviation: Option<Decimal>,
+    pub sample_std_deviation: Option<Decimal>,
+    pub coefficient_of_variation: Option<Decimal>,
+    pub range: Option<Decimal>,
+    pub spread_bps: Option<Decimal>,
+    pub offline: bool,
+    pub timestamp: DateTime<Utc>,
+    pub routes: Vec<RouteReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RouteReport {
+    /// Like `TRY -(exchangeratesapi.io)-> USD`
+    pub route: String,
+    pub providers: Vec<String>,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Why the rate did not count towards the result, e.g. as an outlier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discarded: Option<String>,
+    /// When the oldest rate of the route was fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetched_at: Option<DateTime<Utc>>,
+}
+
+/// An arithmetic expression as printed by `--output json`.
+#[derive(Serialize, Debug)]
+pub struct ExpressionReport {
+    /// Like `120 USD + 35 EUR - 10 GBP`
+    pub expression: String,
+    pub quote: Symbol,
+    pub date: Option<NaiveDate>,
+    pub result: Decimal,
+    pub terms: Vec<TermReport>,
+    /// Rate of every currency of the expression, along with its routes
+    pub conversions: Vec<ConversionReport>,
+    pub offline: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// An amount of an expression converted into the target currency.
+#[derive(Serialize, Debug)]
+pub struct TermReport {
+    pub amount: Decimal,
+    pub base: Symbol,
+    pub result: Decimal,
+    pub rate: Decimal,
+}
+
+/// Daily rates of a range as printed by `--output json`.
+#[derive(Serialize, Debug)]
+pub struct SeriesReport {
+    pub amount: Decimal,
+    pub base: Symbol,
+    pub quote: Symbol,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub rates: Vec<DailyRate>,
+    pub min: DailyRate,
+    pub max: DailyRate,
+    pub mean: Decimal,
+    pub change: Decimal,
+    pub change_percent: Option<Decimal>,
+    pub std_deviation: Option<Decimal>,
+    pub coefficient_of_variation: Option<Decimal>,
+    pub timestamp: DateTime<Utc>,
+    pub providers: Vec<ProviderReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DailyRate {
+    pub date: NaiveDate,
+    pub rate: Decimal,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProviderReport {
+    pub provider: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A converted line of a batch. Fields that could not be determined are left empty.
+#[derive(Serialize, Debug)]
+pub struct BatchRow {
+    pub line: usize,
+    pub amount: Option<Decimal>,
+    pub base: Option<Symbol>,
+    pub quote: Option<Symbol>,
+    pub date: Option<NaiveDate>,
+    pub result: Option<Decimal>,
+    pub rate: Option<Decimal>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Ok,
+    Failed,
+    TimedOut,
+    Dropped,
+}
+
+impl Status {
+    pub fn of<T>(result: &Result<T, anyhow::Error>) -> Status {
+        match result {
+            Ok(_) => Status::Ok,
+            Err(e) if is_timeout(e) => Status::TimedOut,
+            Err(e) if e.chain().any(|c| c.is::<Dropped>()) => Status::Dropped,
+            Err(_) => Status::Failed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RouteReport, Status};
+    use crate::progress_join::Dropped;
+    use crate::providers::http::TimedOut;
+
+    use anyhow::anyhow;
+    use rust_decimal::Decimal;
+    use std::time::Duration;
+
+    #[test]
+    fn serializes_successful_route() {
+        let report = RouteReport {
+            route: String::from("EUR -(exchangeratesapi.io)-> USD"),
+            providers: vec![String::from("exchangeratesapi.io")],
+            status: Status::Ok,
+            rate: Some(Decimal::new(117, 2)),
+            error: None,
+            discarded: None,
+            fetched_at: None,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"route":"EUR -(exchangeratesapi.io)-> USD","providers":["exchangeratesapi.io"],"status":"ok","rate":"1.17"}"#
+        );
+    }
+
+    #[test]
+    fn serializes_failed_route_with_error() {
+        let report = RouteReport {
+            route: String::from("EUR -(fixer.io)-> USD"),
+            providers: vec![String::from("fixer.io")],
+            status: Status::Failed,
+            rate: None,
+            error: Some(String::from("Failed to parse API response")),
+            discarded: None,
+            fetched_at: None,
+        };
+
+        let json = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["error"], "Failed to parse API response");
+        assert!(json.get("rate").is_none());
+    }
+
+    #[test]
+    fn tells_timeouts_and_dropped_requests_apart() {
+        let timed_out: Result<(), _> =
+            Err(anyhow!(TimedOut(Duration::from_secs(10))).context("Failed to fetch"));
+        let dropped: Result<(), _> = Err(anyhow!(Dropped));
+        let failed: Result<(), _> = Err(anyhow!("Failed to fetch"));
+
+        assert_eq!(Status::of(&timed_out), Status::TimedOut);
+        assert_eq!(Status::of(&dropped), Status::Dropped);
+        assert_eq!(Status::of(&failed), Status::Failed);
+        assert_eq!(
+            serde_json::to_value(Status::TimedOut).unwrap(),
+            serde_json::json!("timed-out")
+        );
+    }
+}
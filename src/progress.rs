@@ -0,0 +1,114 @@
+use colored::*;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use std::io::{stderr, IsTerminal, Write};
+use std::time::Duration;
+use tokio::time::{delay_for, Delay, Instant};
+
+use crate::progress_join::Observer;
+
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+/// Time between two frames of the spinners
+const TICK: Duration = Duration::from_millis(100);
+
+/// Outcome of a future as shown by `Progress`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Pending,
+    Succeeded(Duration),
+    Failed(Duration),
+}
+
+/// Renders one line per future on stderr, with a spinner and the elapsed time while it
+/// is pending and whether it succeeded once it is done. Lines are cleared at the end.
+#[derive(Debug)]
+pub struct Progress {
+    labels: Vec<String>,
+    states: Vec<State>,
+    started: Instant,
+    tick: Delay,
+    drawn: bool,
+}
+
+impl Progress {
+    /// Progress of futures labelled like their providers. Nothing is shown unless
+    /// `enabled` and stderr is a terminal, so that output can be piped.
+    pub fn new(labels: Vec<String>, enabled: bool) -> Option<Progress> {
+        if !enabled || !stderr().is_terminal() {
+            return None;
+        }
+
+        Some(Progress {
+            states: vec![State::Pending; labels.len()],
+            labels,
+            started: Instant::now(),
+            tick: delay_for(TICK),
+            drawn: false,
+        })
+    }
+
+    /// Schedules a wake-up for the next frame of the spinners.
+    fn poll_tick(&mut self, cx: &mut Context<'_>) {
+        while Pin::new(&mut self.tick).poll(cx).is_ready() {
+            self.tick.reset(Instant::now() + TICK);
+        }
+    }
+
+    fn render(&mut self) {
+        let elapsed = self.started.elapsed();
+        let frame = SPINNER[(elapsed.as_millis() / TICK.as_millis()) as usize % SPINNER.len()];
+        let mut output = String::new();
+        if self.drawn {
+            output.push_str(&format!("\x1b[{}A", self.labels.len()));
+        }
+        for (label, state) in self.labels.iter().zip(self.states.iter()) {
+            let line = match state {
+                State::Pending => format!("{} {} {}", frame, label, seconds(elapsed).dimmed()),
+                State::Succeeded(after) => format!("{} {} {}", "✓".green(), label, seconds(*after)),
+                State::Failed(after) => format!("{} {} {}", "✗".red(), label, seconds(*after)),
+            };
+            output.push_str(&format!("\r\x1b[2K{}\n", line));
+        }
+
+        self.write(&output);
+        self.drawn = true;
+    }
+
+    fn clear(&mut self) {
+        if self.drawn {
+            self.write(&format!("\x1b[{}A\x1b[J", self.labels.len()));
+            self.drawn = false;
+        }
+    }
+
+    fn write(&self, output: &str) {
+        // Progress is best effort and must not fail the conversion
+        let mut stderr = stderr();
+        let _ = stderr.write_all(output.as_bytes());
+        let _ = stderr.flush();
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+impl<T, E> Observer<Result<T, E>> for Progress {
+    fn completed(&mut self, index: usize, output: &Result<T, E>) {
+        let elapsed = self.started.elapsed();
+        self.states[index] = match output {
+            Ok(_) => State::Succeeded(elapsed),
+            Err(_) => State::Failed(elapsed),
+        };
+    }
+
+    fn pending(&mut self, cx: &mut Context<'_>) {
+        self.render();
+        self.poll_tick(cx);
+    }
+
+    fn finished(&mut self) {
+        self.clear();
+    }
+}
@@ -0,0 +1,524 @@
+use core::fmt;
+use core::future::Future;
+use core::mem;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use std::vec::Vec;
+
+/// Error of a future that was dropped since enough others succeeded before it.
+#[derive(Debug)]
+pub struct Dropped;
+
+impl fmt::Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Dropped since faster responses reached the quorum")
+    }
+}
+
+impl std::error::Error for Dropped {}
+
+/// Gets notified while joined futures make progress, e.g. to render it. All methods
+/// default to doing nothing.
+pub trait Observer<T> {
+    /// The future at `index` among the joined ones completed with `output`.
+    fn completed(&mut self, _index: usize, _output: &T) {}
+
+    /// Some futures are still pending after a poll. `cx` can be used to be woken up
+    /// later, e.g. for the next frame of an animation.
+    fn pending(&mut self, _cx: &mut Context<'_>) {}
+
+    /// No more outputs are awaited, either since all futures completed or since the
+    /// remaining ones are dropped. Called exactly once.
+    fn finished(&mut self) {}
+}
+
+/// Observes nothing.
+impl<T> Observer<T> for () {}
+
+/// Observes if there is an observer, e.g. when progress is only shown on terminals.
+impl<T, O> Observer<T> for Option<O>
+where
+    O: Observer<T>,
+{
+    fn completed(&mut self, index: usize, output: &T) {
+        if let Some(observer) = self {
+            observer.completed(index, output);
+        }
+    }
+
+    fn pending(&mut self, cx: &mut Context<'_>) {
+        if let Some(observer) = self {
+            observer.pending(cx);
+        }
+    }
+
+    fn finished(&mut self) {
+        if let Some(observer) = self {
+            observer.finished();
+        }
+    }
+}
+
+/// A future along with its position among the joined ones.
+#[derive(Debug)]
+struct Indexed<F> {
+    index: usize,
+    future: F,
+}
+
+impl<F> Future for Indexed<F>
+where
+    F: Future,
+{
+    type Output = (usize, F::Output);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `future` is never moved out of `Indexed`, so this has the same safety
+        // as a normal field pin projection.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        future.poll(cx).map(|output| (this.index, output))
+    }
+}
+
+/// Stream of the outputs of futures along with their index, in the order they
+/// complete. Only futures that were woken are polled again. The observer is told about
+/// each output before it is yielded, and is finished once the stream ends or is dropped.
+pub struct Completions<F, O>
+where
+    F: Future,
+    O: Observer<F::Output>,
+{
+    futures: FuturesUnordered<Indexed<F>>,
+    observer: O,
+    finished: bool,
+}
+
+/// Streams the outputs of `futures` as they complete, see `Completions`.
+pub fn completions<I, O>(futures: I, observer: O) -> Completions<I::Item, O>
+where
+    I: IntoIterator,
+    I::Item: Future,
+    O: Observer<<I::Item as Future>::Output>,
+{
+    Completions {
+        futures: futures
+            .into_iter()
+            .enumerate()
+            .map(|(index, future)| Indexed { index, future })
+            .collect(),
+        observer,
+        finished: false,
+    }
+}
+
+impl<F, O> Completions<F, O>
+where
+    F: Future,
+    O: Observer<F::Output>,
+{
+    /// Number of futures that did not complete yet.
+    pub fn pending(&self) -> usize {
+        self.futures.len()
+    }
+
+    fn finish(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            self.observer.finished();
+        }
+    }
+}
+
+// The observer is never pinned and `FuturesUnordered` is always `Unpin`
+impl<F, O> Unpin for Completions<F, O>
+where
+    F: Future,
+    O: Observer<F::Output>,
+{
+}
+
+impl<F, O> Stream for Completions<F, O>
+where
+    F: Future,
+    O: Observer<F::Output>,
+{
+    type Item = (usize, F::Output);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        match self.futures.poll_next_unpin(cx) {
+            Poll::Ready(Some((index, output))) => {
+                self.observer.completed(index, &output);
+                Poll::Ready(Some((index, output)))
+            }
+            Poll::Ready(None) => {
+                self.finish();
+                Poll::Ready(None)
+            }
+            Poll::Pending => {
+                self.observer.pending(cx);
+                Poll::Pending
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.finished {
+            true => (0, Some(0)),
+            _ => (self.futures.len(), Some(self.futures.len())),
+        }
+    }
+}
+
+impl<F, O> Drop for Completions<F, O>
+where
+    F: Future,
+    O: Observer<F::Output>,
+{
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl<F, O> fmt::Debug for Completions<F, O>
+where
+    F: Future,
+    O: Observer<F::Output>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Completions")
+            .field("pending", &self.futures.len())
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+/// Future of `join_quorum`.
+pub struct JoinQuorum<F, O>
+where
+    F: Future,
+    O: Observer<F::Output>,
+{
+    completions: Option<Completions<F, O>>,
+    outputs: Vec<Option<F::Output>>,
+    quorum: usize,
+    succeeded: usize,
+}
+
+/// Awaits the futures while telling the `observer` about their progress, and resolves
+/// as soon as `quorum` of them succeeded, dropping the others. Outputs keep the order
+/// of the futures and are `None` for dropped ones. Without a quorum, all futures are
+/// awaited.
+pub fn join_quorum<I, O, T, E>(
+    futures: I,
+    quorum: Option<usize>,
+    observer: O,
+) -> JoinQuorum<I::Item, O>
+where
+    I: IntoIterator,
+    I::Item: Future<Output = Result<T, E>>,
+    O: Observer<Result<T, E>>,
+{
+    let completions = completions(futures, observer);
+    let len = completions.pending();
+    JoinQuorum {
+        completions: Some(completions),
+        outputs: (0..len).map(|_| None).collect(),
+        quorum: quorum.unwrap_or(len),
+        succeeded: 0,
+    }
+}
+
+// Outputs are never pinned
+impl<F, O> Unpin for JoinQuorum<F, O>
+where
+    F: Future,
+    O: Observer<F::Output>,
+{
+}
+
+impl<F, O, T, E> Future for JoinQuorum<F, O>
+where
+    F: Future<Output = Result<T, E>>,
+    O: Observer<F::Output>,
+{
+    type Output = Vec<Option<F::Output>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let completions = this
+            .completions
+            .as_mut()
+            .expect("JoinQuorum polled after completion");
+
+        while this.succeeded < this.quorum {
+            match completions.poll_next_unpin(cx) {
+                Poll::Ready(Some((index, output))) => {
+                    if output.is_ok() {
+                        this.succeeded += 1;
+                    }
+                    this.outputs[index] = Some(output);
+                }
+                Poll::Ready(None) => break,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        // Drops the futures still pending and finishes the observer
+        this.completions = None;
+        Poll::Ready(mem::take(&mut this.outputs))
+    }
+}
+
+impl<F, O> fmt::Debug for JoinQuorum<F, O>
+where
+    F: Future,
+    F::Output: fmt::Debug,
+    O: Observer<F::Output>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinQuorum")
+            .field("completions", &self.completions)
+            .field("outputs", &self.outputs)
+            .field("quorum", &self.quorum)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{completions, join_quorum, Observer};
+
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll, Waker};
+    use futures::future::{pending, ready, BoxFuture, FutureExt};
+    use futures::stream::{Stream, StreamExt};
+    use futures::task::noop_waker;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use tokio::time::delay_for;
+
+    type Fetch = BoxFuture<'static, Result<u32, &'static str>>;
+    type Output = Result<u32, &'static str>;
+
+    #[derive(Default)]
+    struct Shared {
+        output: Option<Output>,
+        waker: Option<Waker>,
+        polls: usize,
+    }
+
+    /// Future that is completed by hand through its `Handle`.
+    struct Manual(Rc<RefCell<Shared>>);
+
+    impl Future for Manual {
+        type Output = Output;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Output> {
+            let mut shared = self.0.borrow_mut();
+            shared.polls += 1;
+            match shared.output.take() {
+                Some(output) => Poll::Ready(output),
+                None => {
+                    shared.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    struct Handle(Rc<RefCell<Shared>>);
+
+    impl Handle {
+        fn complete(&self, output: Output) {
+            let mut shared = self.0.borrow_mut();
+            shared.output = Some(output);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }
+
+        fn polls(&self) -> usize {
+            self.0.borrow().polls
+        }
+
+        fn is_dropped(&self) -> bool {
+            Rc::strong_count(&self.0) == 1
+        }
+    }
+
+    fn manual(count: usize) -> (Vec<Manual>, Vec<Handle>) {
+        (0..count)
+            .map(|_| {
+                let shared = Rc::new(RefCell::new(Shared::default()));
+                (Manual(shared.clone()), Handle(shared))
+            })
+            .unzip()
+    }
+
+    /// Records the calls it gets.
+    #[derive(Clone, Default)]
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl Recorder {
+        fn take(&self) -> Vec<String> {
+            self.0.borrow_mut().drain(..).collect()
+        }
+    }
+
+    impl Observer<Output> for Recorder {
+        fn completed(&mut self, index: usize, output: &Output) {
+            self.0.borrow_mut().push(format!("{}: {:?}", index, output));
+        }
+
+        fn pending(&mut self, _cx: &mut Context<'_>) {
+            self.0.borrow_mut().push("pending".to_string());
+        }
+
+        fn finished(&mut self) {
+            self.0.borrow_mut().push("finished".to_string());
+        }
+    }
+
+    fn poll<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        let waker = noop_waker();
+        Pin::new(future).poll(&mut Context::from_waker(&waker))
+    }
+
+    fn poll_next<S: Stream + Unpin>(stream: &mut S) -> Poll<Option<S::Item>> {
+        let waker = noop_waker();
+        stream.poll_next_unpin(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn polls_only_woken_futures() {
+        let (futures, handles) = manual(3);
+        let recorder = Recorder::default();
+        let mut join = join_quorum(futures, None, recorder.clone());
+
+        assert!(poll(&mut join).is_pending());
+        handles[1].complete(Ok(2));
+        assert!(poll(&mut join).is_pending());
+
+        let polls = handles.iter().map(Handle::polls).collect::<Vec<_>>();
+        assert_eq!(polls, vec![1, 2, 1]);
+        assert_eq!(recorder.take(), vec!["pending", "1: Ok(2)", "pending"]);
+    }
+
+    #[test]
+    fn streams_outputs_in_order_of_completion() {
+        let (futures, handles) = manual(3);
+        let recorder = Recorder::default();
+        let mut stream = completions(futures, recorder.clone());
+
+        assert_eq!(poll_next(&mut stream), Poll::Pending);
+        handles[2].complete(Err("failed"));
+        handles[0].complete(Ok(1));
+        assert_eq!(
+            poll_next(&mut stream),
+            Poll::Ready(Some((2, Err("failed"))))
+        );
+        assert_eq!(poll_next(&mut stream), Poll::Ready(Some((0, Ok(1)))));
+        assert_eq!(stream.pending(), 1);
+        assert_eq!(poll_next(&mut stream), Poll::Pending);
+        handles[1].complete(Ok(2));
+        assert_eq!(poll_next(&mut stream), Poll::Ready(Some((1, Ok(2)))));
+        assert_eq!(poll_next(&mut stream), Poll::Ready(None));
+        assert_eq!(poll_next(&mut stream), Poll::Ready(None));
+        drop(stream);
+
+        assert_eq!(
+            recorder.take(),
+            vec![
+                "pending",
+                "2: Err(\"failed\")",
+                "0: Ok(1)",
+                "pending",
+                "1: Ok(2)",
+                "finished"
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_pending_futures_once_quorum_succeeded() {
+        let (futures, handles) = manual(3);
+        let recorder = Recorder::default();
+        let mut join = join_quorum(futures, Some(1), recorder.clone());
+
+        assert!(poll(&mut join).is_pending());
+        handles[0].complete(Err("failed"));
+        assert!(poll(&mut join).is_pending());
+        handles[1].complete(Ok(2));
+
+        assert_eq!(
+            poll(&mut join),
+            Poll::Ready(vec![Some(Err("failed")), Some(Ok(2)), None])
+        );
+        assert!(handles[2].is_dropped());
+        assert_eq!(
+            recorder.take(),
+            vec![
+                "pending",
+                "0: Err(\"failed\")",
+                "pending",
+                "1: Ok(2)",
+                "finished"
+            ]
+        );
+    }
+
+    #[test]
+    fn finishes_observer_when_dropped() {
+        let (futures, _handles) = manual(2);
+        let recorder = Recorder::default();
+        let mut join = join_quorum(futures, None, recorder.clone());
+
+        assert!(poll(&mut join).is_pending());
+        drop(join);
+
+        assert_eq!(recorder.take(), vec!["pending", "finished"]);
+    }
+
+    #[tokio::test]
+    async fn resolves_once_quorum_succeeded() {
+        let futures: Vec<Fetch> = vec![
+            pending().boxed(),
+            async {
+                delay_for(Duration::from_millis(5)).await;
+                Ok(1)
+            }
+            .boxed(),
+            ready(Err("failed")).boxed(),
+        ];
+
+        let results = join_quorum(futures, Some(1), ()).await;
+
+        assert_eq!(results, vec![None, Some(Ok(1)), Some(Err("failed"))]);
+    }
+
+    #[tokio::test]
+    async fn awaits_all_without_quorum() {
+        let futures: Vec<Fetch> = vec![ready(Ok(1)).boxed(), ready(Err("failed")).boxed()];
+
+        let results = join_quorum(futures, None, ()).await;
+
+        assert_eq!(results, vec![Some(Ok(1)), Some(Err("failed"))]);
+    }
+
+    #[tokio::test]
+    async fn awaits_all_if_quorum_cannot_be_reached() {
+        let futures: Vec<Fetch> = vec![ready(Err("failed")).boxed(), ready(Ok(2)).boxed()];
+
+        let results = join_quorum(futures, Some(2), ()).await;
+
+        assert_eq!(results, vec![Some(Err("failed")), Some(Ok(2))]);
+    }
+}
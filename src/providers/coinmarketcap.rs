@@ -1,16 +1,23 @@
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use async_trait::async_trait;
+use chrono::NaiveDate;
 use reqwest::Url;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
 use crate::currency::Symbol;
-use crate::providers::provider::{BaseProvider, Provider};
+use crate::providers::error::ProviderError;
+use crate::providers::http::{Http, HttpResponse};
+use crate::providers::provider::{BaseProvider, Provider, Usage};
 use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub const NAME: &str = "coinmarketcap.com";
 
 pub struct CoinMarketCapProvider {
     provider: BaseProvider,
     access_key: String,
+    usage: Mutex<Usage>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -32,12 +39,71 @@ struct Quote {
     last_updated: String,
 }
 
+/// Status block sent along with every response, whose error code is not 0 if the
+/// request failed
+#[derive(Deserialize)]
+struct StatusResponse {
+    status: Status,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    error_code: i64,
+    error_message: Option<String>,
+    /// Credits charged for the request
+    #[serde(default)]
+    credit_count: u64,
+    /// Warnings about the plan, e.g. that most of its credits are used
+    notice: Option<String>,
+}
+
+impl From<Status> for ProviderError {
+    fn from(status: Status) -> Self {
+        let message = status
+            .error_message
+            .unwrap_or_else(|| String::from("Unknown error"));
+
+        match status.error_code {
+            // Invalid, missing, disabled or IP restricted key
+            1001 | 1002 | 1005 | 1007 => ProviderError::InvalidApiKey(message),
+            // Plan not paid or not including the endpoint, invalid values of a request
+            400 | 1003 | 1004 | 1006 => ProviderError::Unsupported(message),
+            // Rate limits per minute, day and month, or by IP
+            1008..=1011 => ProviderError::RateLimited(message),
+            code => ProviderError::Api { code, message },
+        }
+    }
+}
+
+/// Fails with the error reported by the API, if the response is one.
+fn check_status(response: &str) -> Result<(), ProviderError> {
+    match serde_json::from_str::<StatusResponse>(response) {
+        Ok(StatusResponse { status }) if status.error_code != 0 => Err(status.into()),
+        _ => Ok(()),
+    }
+}
+
 #[async_trait]
 impl Provider for CoinMarketCapProvider {
     fn get_name(&self) -> String {
         self.provider.name.clone()
     }
-    fn build_url(&self, base: &Symbol, quote: &Symbol) -> Result<Url, anyhow::Error> {
+    fn supports(&self, _symbol: &Symbol) -> bool {
+        true
+    }
+    // Historical quotes require a paid plan and are thus not supported
+    fn build_url(
+        &self,
+        base: &Symbol,
+        quote: &Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Url, ProviderError> {
+        if date.is_some() {
+            return Err(ProviderError::Unsupported(String::from(
+                "Historical rates are not supported",
+            )));
+        }
+
         Url::parse_with_params(
             &self.provider.base_url,
             &[
@@ -47,62 +113,92 @@ impl Provider for CoinMarketCapProvider {
             ],
         )
         .context("Failed to build URL")
+        .map_err(ProviderError::Other)
     }
-    async fn get_rate(&self, base: Symbol, quote: Symbol) -> Result<Decimal, anyhow::Error> {
-        let url = self.build_url(&base, &quote)?;
-        let client = reqwest::Client::new();
-        let resp = client
-            .get(url)
-            .header("X-CMC_PRO_API_KEY", self.access_key.clone())
-            .send()
-            .await?
-            .text()
+    async fn get_rate(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Decimal, ProviderError> {
+        let url = self.build_url(&base, &quote, date)?;
+        let resp = self
+            .provider
+            .http
+            .get(url, &[("X-CMC_PRO_API_KEY", &self.access_key)])
             .await?;
+        self.record(&resp);
 
-        let parsed_rate = CoinMarketCapProvider::parse_rate_from_response(&self, &quote, &resp)?;
+        let parsed_rate =
+            CoinMarketCapProvider::parse_rate_from_response(&self, &quote, &resp.body)?;
 
         Ok(parsed_rate)
     }
+    fn usage(&self) -> Option<Usage> {
+        Some(self.usage.lock().unwrap().clone())
+    }
     fn parse_rate_from_response(
         &self,
         quote: &Symbol,
         response: &str,
-    ) -> Result<Decimal, anyhow::Error> {
-        Ok(serde_json::from_str::<Response>(response)
-            .context("Failed to parse API response")?
+    ) -> Result<Decimal, ProviderError> {
+        check_status(response)?;
+        serde_json::from_str::<Response>(response)?
             .data
             .quote
             .get(quote)
-            .context("Failed to find quote symbol in parsed API response")?
-            .price)
+            .map(|q| q.price)
+            .ok_or_else(|| {
+                ProviderError::MalformedResponse(anyhow!(
+                    "Failed to find quote symbol in parsed API response"
+                ))
+            })
     }
 }
 
 impl CoinMarketCapProvider {
-    pub fn new(access_key: String) -> Self {
+    pub fn new(access_key: String, http: Http) -> Self {
         Self {
             provider: BaseProvider {
-                name: String::from("coinmarketcap.com"),
+                name: String::from(NAME),
                 base_url: String::from(
                     "https://pro-api.coinmarketcap.com/v1/tools/price-conversion",
                 ),
+                http,
             },
             access_key,
+            usage: Mutex::default(),
         }
     }
+
+    /// Counts the credits the API charged for a request, along with its notices.
+    fn record(&self, response: &HttpResponse) {
+        let status = serde_json::from_str::<StatusResponse>(&response.body).map(|r| r.status);
+        let (credits, notice) = match status {
+            Ok(status) => (status.credit_count, status.notice),
+            Err(_) => (0, None),
+        };
+        self.usage
+            .lock()
+            .unwrap()
+            .record(credits, response.quota, notice);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::CoinMarketCapProvider;
     use crate::currency::Symbol;
+    use crate::providers::error::ProviderError;
+    use crate::providers::http::{Http, HttpResponse};
     use crate::providers::provider::Provider;
 
     use rust_decimal::Decimal;
+    use std::str::FromStr;
 
     #[test]
     fn parses_response_correctly() {
-        let quote = Symbol::USD;
+        let quote = Symbol::from_str("USD").unwrap();
         let expected_rate = Decimal::new(111, 4);
         let response = format!(
             r#"
@@ -132,7 +228,7 @@ mod tests {
         "#,
             expected_rate
         );
-        let provider = CoinMarketCapProvider::new(String::from("some-access-key"));
+        let provider = CoinMarketCapProvider::new(String::from("some-access-key"), Http::default());
 
         // Note: Converting to Option to get rid of E in Result<T,E>. Otherwise,
         // the assertion fails as anyhow::Error does not implement Eq.
@@ -143,7 +239,7 @@ mod tests {
 
     #[test]
     fn fails_parsing_invalid_response() {
-        let quote = Symbol::USD;
+        let quote = Symbol::from_str("USD").unwrap();
         let response = r#"
             {{
                 "status": {{
@@ -169,12 +265,61 @@ mod tests {
                 }}
             }}
         "#;
-        let provider = CoinMarketCapProvider::new(String::from("some-access-key"));
+        let provider = CoinMarketCapProvider::new(String::from("some-access-key"), Http::default());
 
         // Note: Converting to Option to get rid of E in Result<T,E>. Otherwise,
         // the assertion fails as anyhow::Error does not implement Eq.
-        let rate = provider.parse_rate_from_response(&quote, &response);
+        let rate = provider.parse_rate_from_response(&quote, response);
 
         assert!(rate.is_err(), "Parsing invalid response should fail");
     }
+
+    #[test]
+    fn maps_error_responses() {
+        let quote = Symbol::from_str("USD").unwrap();
+        let response = |code: i64| {
+            format!(
+                r#"{{"status": {{"error_code": {}, "error_message": "Some message.", "credit_count": 0}}}}"#,
+                code
+            )
+        };
+        let provider = CoinMarketCapProvider::new(String::from("some-access-key"), Http::default());
+        let parse = |code| provider.parse_rate_from_response(&quote, &response(code));
+
+        assert!(
+            matches!(parse(1002), Err(ProviderError::InvalidApiKey(m)) if m == "Some message.")
+        );
+        assert!(matches!(parse(1006), Err(ProviderError::Unsupported(_))));
+        assert!(matches!(parse(1009), Err(ProviderError::RateLimited(_))));
+        assert!(matches!(
+            parse(500),
+            Err(ProviderError::Api { code: 500, .. })
+        ));
+    }
+
+    #[test]
+    fn records_credits_and_notices() {
+        let response = |credits: u64, notice: &str| HttpResponse {
+            body: format!(
+                r#"{{"status": {{"error_code": 0, "credit_count": {}, "notice": {}}}}}"#,
+                credits, notice
+            ),
+            quota: None,
+        };
+        let provider = CoinMarketCapProvider::new(String::from("some-access-key"), Http::default());
+
+        provider.record(&response(
+            1,
+            r#""You have used 95% of your plan's credits.""#,
+        ));
+        provider.record(&response(2, "null"));
+        let usage = provider.usage().unwrap();
+
+        assert_eq!(usage.credits, 3);
+        assert_eq!(
+            usage.notice.as_deref(),
+            Some("You have used 95% of your plan's credits.")
+        );
+        assert!(usage.is_running_out());
+    }
 }
@@ -0,0 +1,99 @@
+use std::error::Error;
+use std::fmt;
+
+/// Why a provider could not serve rates. API specific error payloads are mapped into
+/// these, so that callers can tell an invalid access key from a used up quota.
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The access key is missing, invalid or disabled
+    InvalidApiKey(String),
+    /// Too many requests, or the quota of the plan is used up
+    RateLimited(String),
+    /// The currency, date or endpoint is not served by the provider or its plan
+    Unsupported(String),
+    /// The request did not get a usable response, e.g. since the connection failed
+    Network(anyhow::Error),
+    /// The response could not be parsed
+    MalformedResponse(anyhow::Error),
+    /// Any other error reported by the API
+    Api { code: i64, message: String },
+    /// Errors outside of requests, e.g. failing to build a URL
+    Other(anyhow::Error),
+}
+
+impl ProviderError {
+    /// Finds the provider error among the causes of `error`.
+    pub fn find(error: &anyhow::Error) -> Option<&ProviderError> {
+        error
+            .chain()
+            .find_map(|e| e.downcast_ref::<ProviderError>())
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProviderError::InvalidApiKey(message) => write!(f, "Invalid access key: {}", message),
+            ProviderError::RateLimited(message) => write!(f, "Rate limited: {}", message),
+            ProviderError::Unsupported(message) => write!(f, "{}", message),
+            ProviderError::Network(_) => write!(f, "Request failed"),
+            ProviderError::MalformedResponse(_) => write!(f, "Failed to parse API response"),
+            ProviderError::Api { code, message } => write!(f, "API error {}: {}", code, message),
+            ProviderError::Other(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for ProviderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProviderError::Network(error) | ProviderError::MalformedResponse(error) => {
+                Some(error.as_ref())
+            }
+            // Displayed in place of this error, so its causes follow right away
+            ProviderError::Other(error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ProviderError {
+    fn from(error: serde_json::Error) -> Self {
+        ProviderError::MalformedResponse(error.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProviderError;
+    use crate::providers::http::{is_timeout, TimedOut};
+
+    use anyhow::{anyhow, Context};
+    use std::time::Duration;
+
+    #[test]
+    fn finds_error_among_causes() {
+        let error = Err::<(), _>(ProviderError::InvalidApiKey(String::from("No key")))
+            .context("Failed to fetch")
+            .unwrap_err();
+
+        assert!(matches!(
+            ProviderError::find(&error),
+            Some(ProviderError::InvalidApiKey(_))
+        ));
+        assert!(ProviderError::find(&anyhow!("Failed to fetch")).is_none());
+    }
+
+    #[test]
+    fn keeps_causes_of_network_errors() {
+        let error = anyhow!(ProviderError::Network(anyhow!(TimedOut(
+            Duration::from_secs(1)
+        ))));
+
+        assert!(is_timeout(&error));
+        assert_eq!(
+            format!("{:#}", error),
+            "Request failed: Timed out after 1.0s"
+        );
+    }
+}
@@ -1,15 +1,29 @@
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use async_trait::async_trait;
+use chrono::NaiveDate;
 use reqwest::Url;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
 use crate::currency::Symbol;
-use crate::providers::provider::{BaseProvider, Provider};
+use crate::providers::error::ProviderError;
+use crate::providers::http::Http;
+use crate::providers::provider::{
+    check_date_range, BaseProvider, FetchedRates, Provider, Rates, Series,
+};
 use std::collections::HashMap;
 
 pub struct ExchangeRatesApiProvider(BaseProvider);
 
+pub const NAME: &str = "exchangeratesapi.io";
+
+// Reference rates published by the European Central Bank
+const SUPPORTED_SYMBOLS: &[&str] = &[
+    "AUD", "BGN", "BRL", "CAD", "CHF", "CNY", "CZK", "DKK", "EUR", "GBP", "HKD", "HUF", "IDR",
+    "ILS", "INR", "ISK", "JPY", "KRW", "MXN", "MYR", "NOK", "NZD", "PHP", "PLN", "RON", "RUB",
+    "SEK", "SGD", "THB", "TRY", "USD", "ZAR",
+];
+
 #[derive(Serialize, Deserialize)]
 struct Response {
     rates: HashMap<Symbol, Decimal>,
@@ -17,61 +31,276 @@ struct Response {
     date: String,
 }
 
+#[derive(Serialize, Deserialize)]
+struct HistoryResponse {
+    rates: HashMap<NaiveDate, HashMap<Symbol, Decimal>>,
+    base: Symbol,
+    start_at: NaiveDate,
+    end_at: NaiveDate,
+}
+
+/// Body of failed requests, e.g. `{"error": "Base 'XYZ' is not supported."}`
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// Fails with the error reported by the API, if the response is one. The API only
+/// rejects symbols and dates it does not serve.
+fn check_error(response: &str) -> Result<(), ProviderError> {
+    match serde_json::from_str::<ErrorResponse>(response) {
+        Ok(ErrorResponse { error }) => Err(ProviderError::Unsupported(error)),
+        _ => Ok(()),
+    }
+}
+
 #[async_trait]
 impl Provider for ExchangeRatesApiProvider {
     fn get_name(&self) -> String {
         self.0.name.clone()
     }
-    fn build_url(&self, base: &Symbol, quote: &Symbol) -> Result<Url, anyhow::Error> {
-        Url::parse_with_params(
-            &self.0.base_url,
-            &[("base", base.to_string()), ("symbols", quote.to_string())],
-        )
-        .context("Failed to build URL")
+    fn supports(&self, symbol: &Symbol) -> bool {
+        SUPPORTED_SYMBOLS.contains(&symbol.as_str())
+    }
+    fn supports_date(&self, date: &NaiveDate) -> Result<(), ProviderError> {
+        check_date_range(date, NaiveDate::from_ymd_opt(1999, 1, 4).unwrap())
     }
-    async fn get_rate(&self, base: Symbol, quote: Symbol) -> Result<Decimal, anyhow::Error> {
-        let url = self.build_url(&base, &quote)?;
-        let client = reqwest::Client::new();
-        let resp = client.get(url).send().await?.text().await?;
+    fn build_url(
+        &self,
+        base: &Symbol,
+        quote: &Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Url, ProviderError> {
+        self.build_rates_url(base, &[*quote], date)
+    }
+    async fn get_rate(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Decimal, ProviderError> {
+        let url = self.build_url(&base, &quote, date)?;
+        let resp = self.0.http.get(url, &[]).await?.body;
 
         let parsed_rate = ExchangeRatesApiProvider::parse_rate_from_response(&self, &quote, &resp)?;
 
         Ok(parsed_rate)
     }
+    async fn get_rates(
+        &self,
+        base: Symbol,
+        quotes: &[Symbol],
+        date: Option<NaiveDate>,
+    ) -> Result<FetchedRates, ProviderError> {
+        let url = self.build_rates_url(&base, quotes, date)?;
+        let resp = self.0.http.get(url, &[]).await?.body;
+
+        let mut rates = self.parse_rates_from_response(&resp)?;
+        rates.retain(|symbol, _| quotes.contains(symbol));
+
+        Ok(rates.into())
+    }
+    async fn get_series(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Series, ProviderError> {
+        let url = self.build_series_url(&base, &quote, from, to)?;
+        let resp = self.0.http.get(url, &[]).await?.body;
+
+        self.parse_series_from_response(&quote, &resp)
+    }
     fn parse_rate_from_response(
         &self,
         quote: &Symbol,
         response: &str,
-    ) -> Result<Decimal, anyhow::Error> {
-        serde_json::from_str::<Response>(response)
-            .context("Failed to parse API response")?
-            .rates
+    ) -> Result<Decimal, ProviderError> {
+        self.parse_rates_from_response(response)?
             .get(quote)
             .cloned()
-            .context("Failed to find quote symbol in parsed API response")
+            .ok_or_else(|| {
+                ProviderError::MalformedResponse(anyhow!(
+                    "Failed to find quote symbol in parsed API response"
+                ))
+            })
     }
 }
 
 impl ExchangeRatesApiProvider {
-    pub fn new() -> Self {
+    pub fn new(http: Http) -> Self {
         Self(BaseProvider {
-            name: String::from("exchangeratesapi.io"),
-            base_url: String::from("https://api.exchangeratesapi.io/latest"),
+            name: String::from(NAME),
+            base_url: String::from("https://api.exchangeratesapi.io"),
+            http,
         })
     }
+
+    fn build_rates_url(
+        &self,
+        base: &Symbol,
+        quotes: &[Symbol],
+        date: Option<NaiveDate>,
+    ) -> Result<Url, ProviderError> {
+        let endpoint = date
+            .map(|d| d.to_string())
+            .unwrap_or_else(|| String::from("latest"));
+        let symbols = quotes
+            .iter()
+            .map(|q| q.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Url::parse_with_params(
+            &format!("{}/{}", self.0.base_url, endpoint),
+            &[("base", base.to_string()), ("symbols", symbols)],
+        )
+        .context("Failed to build URL")
+        .map_err(ProviderError::Other)
+    }
+
+    fn parse_rates_from_response(&self, response: &str) -> Result<Rates, ProviderError> {
+        check_error(response)?;
+        Ok(serde_json::from_str::<Response>(response)?.rates)
+    }
+
+    fn build_series_url(
+        &self,
+        base: &Symbol,
+        quote: &Symbol,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Url, ProviderError> {
+        Url::parse_with_params(
+            &format!("{}/history", self.0.base_url),
+            &[
+                ("start_at", from.to_string()),
+                ("end_at", to.to_string()),
+                ("base", base.to_string()),
+                ("symbols", quote.to_string()),
+            ],
+        )
+        .context("Failed to build URL")
+        .map_err(ProviderError::Other)
+    }
+
+    fn parse_series_from_response(
+        &self,
+        quote: &Symbol,
+        response: &str,
+    ) -> Result<Series, ProviderError> {
+        check_error(response)?;
+        serde_json::from_str::<HistoryResponse>(response)?
+            .rates
+            .into_iter()
+            .map(|(date, rates)| {
+                rates.get(quote).map(|rate| (date, *rate)).ok_or_else(|| {
+                    ProviderError::MalformedResponse(anyhow!(
+                        "Failed to find quote symbol for {}",
+                        date
+                    ))
+                })
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::ExchangeRatesApiProvider;
     use crate::currency::Symbol;
+    use crate::providers::error::ProviderError;
+    use crate::providers::http::Http;
     use crate::providers::provider::Provider;
 
+    use chrono::NaiveDate;
     use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    #[test]
+    fn builds_latest_and_historical_urls() {
+        let base = Symbol::from_str("EUR").unwrap();
+        let quote = Symbol::from_str("USD").unwrap();
+        let provider = ExchangeRatesApiProvider::new(Http::default());
+
+        let latest = provider.build_url(&base, &quote, None).unwrap();
+        let historical = provider
+            .build_url(
+                &base,
+                &quote,
+                Some(NaiveDate::from_ymd_opt(2020, 3, 15).unwrap()),
+            )
+            .unwrap();
+
+        assert_eq!(latest.path(), "/latest");
+        assert_eq!(historical.path(), "/2020-03-15");
+    }
+
+    #[test]
+    fn builds_url_for_several_quotes() {
+        let base = Symbol::from_str("EUR").unwrap();
+        let quotes = [
+            Symbol::from_str("USD").unwrap(),
+            Symbol::from_str("GBP").unwrap(),
+        ];
+        let provider = ExchangeRatesApiProvider::new(Http::default());
+
+        let url = provider.build_rates_url(&base, &quotes, None).unwrap();
+
+        assert_eq!(url.query(), Some("base=EUR&symbols=USD%2CGBP"));
+    }
+
+    #[test]
+    fn rejects_dates_before_first_reference_rates() {
+        let provider = ExchangeRatesApiProvider::new(Http::default());
+
+        assert!(provider
+            .supports_date(&NaiveDate::from_ymd_opt(2020, 3, 15).unwrap())
+            .is_ok());
+        assert!(provider
+            .supports_date(&NaiveDate::from_ymd_opt(1998, 12, 31).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn parses_history_response() {
+        let quote = Symbol::from_str("USD").unwrap();
+        let response = r#"
+            {
+                "rates": {
+                    "2020-01-03": { "USD": "1.1147" },
+                    "2020-01-02": { "USD": "1.1193" }
+                },
+                "start_at": "2020-01-01",
+                "end_at": "2020-01-03",
+                "base": "EUR"
+            }
+        "#;
+        let provider = ExchangeRatesApiProvider::new(Http::default());
+
+        let series = provider
+            .parse_series_from_response(&quote, response)
+            .unwrap();
+
+        assert_eq!(
+            series.into_iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    NaiveDate::from_ymd_opt(2020, 1, 2).unwrap(),
+                    Decimal::new(11193, 4)
+                ),
+                (
+                    NaiveDate::from_ymd_opt(2020, 1, 3).unwrap(),
+                    Decimal::new(11147, 4)
+                ),
+            ]
+        );
+    }
 
     #[test]
     fn parses_response_correctly() {
-        let quote = Symbol::USD;
+        let quote = Symbol::from_str("USD").unwrap();
         let expected_rate = Decimal::new(111, 4);
         let response = format!(
             r#"
@@ -85,7 +314,7 @@ mod tests {
         "#,
             expected_rate
         );
-        let provider = ExchangeRatesApiProvider::new();
+        let provider = ExchangeRatesApiProvider::new(Http::default());
 
         // Note: Converting to Option to get rid of E in Result<T,E>. Otherwise,
         // the assertion fails as anyhow::Error does not implement Eq.
@@ -96,7 +325,7 @@ mod tests {
 
     #[test]
     fn fails_parsing_invalid_response() {
-        let quote = Symbol::USD;
+        let quote = Symbol::from_str("USD").unwrap();
         let response = r#"
             {{
                 "rates": {{
@@ -106,12 +335,23 @@ mod tests {
                 "date": "2020-09-23"
             }}
         "#;
-        let provider = ExchangeRatesApiProvider::new();
+        let provider = ExchangeRatesApiProvider::new(Http::default());
 
         // Note: Converting to Option to get rid of E in Result<T,E>. Otherwise,
         // the assertion fails as anyhow::Error does not implement Eq.
-        let rate = provider.parse_rate_from_response(&quote, &response);
+        let rate = provider.parse_rate_from_response(&quote, response);
 
         assert!(rate.is_err(), "Parsing invalid response should fail");
     }
+
+    #[test]
+    fn maps_error_responses() {
+        let quote = Symbol::from_str("USD").unwrap();
+        let response = r#"{"error": "Base 'XYZ' is not supported."}"#;
+        let provider = ExchangeRatesApiProvider::new(Http::default());
+
+        let rate = provider.parse_rate_from_response(&quote, response);
+
+        assert!(matches!(rate, Err(ProviderError::Unsupported(m)) if m.contains("XYZ")));
+    }
 }
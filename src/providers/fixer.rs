@@ -1,16 +1,25 @@
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use async_trait::async_trait;
+use chrono::NaiveDate;
 use reqwest::Url;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use crate::currency::Symbol;
-use crate::providers::provider::{BaseProvider, Provider};
+use crate::currency::{self, CurrencyType, Symbol};
+use crate::providers::error::ProviderError;
+use crate::providers::http::{Http, HttpResponse};
+use crate::providers::provider::{
+    check_date_range, BaseProvider, FetchedRates, Provider, Rates, Series, Usage,
+};
 use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub const NAME: &str = "fixer.io";
 
 pub struct FixerProvider {
     provider: BaseProvider,
     access_key: String,
+    usage: Mutex<Usage>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -22,68 +31,280 @@ struct Response {
     success: bool,
 }
 
+#[derive(Serialize, Deserialize)]
+struct TimeseriesResponse {
+    rates: HashMap<NaiveDate, HashMap<Symbol, Decimal>>,
+    base: Symbol,
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    timeseries: bool,
+    success: bool,
+}
+
+/// Envelope of failed requests, e.g.
+/// `{"success": false, "error": {"code": 101, "type": "invalid_access_key", "info": ".."}}`
+#[derive(Deserialize)]
+struct ErrorResponse {
+    success: bool,
+    error: Option<ApiError>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    code: i64,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    info: Option<String>,
+}
+
+impl From<ApiError> for ProviderError {
+    fn from(error: ApiError) -> Self {
+        let message = error
+            .info
+            .or(error.kind)
+            .unwrap_or_else(|| String::from("Unknown error"));
+
+        match error.code {
+            // Missing, invalid or inactive access key
+            101 | 102 => ProviderError::InvalidApiKey(message),
+            // Monthly request volume reached
+            104 => ProviderError::RateLimited(message),
+            // Endpoint, HTTPS or base currency not included in the plan, invalid symbols
+            105 | 106 | 201 | 202 => ProviderError::Unsupported(message),
+            code => ProviderError::Api { code, message },
+        }
+    }
+}
+
+/// Fails with the error reported by the API, if the response is one.
+fn check_error(response: &str) -> Result<(), ProviderError> {
+    match serde_json::from_str::<ErrorResponse>(response) {
+        Ok(ErrorResponse {
+            success: false,
+            error: Some(error),
+        }) => Err(error.into()),
+        _ => Ok(()),
+    }
+}
+
 #[async_trait]
 impl Provider for FixerProvider {
     fn get_name(&self) -> String {
         self.provider.name.clone()
     }
-    fn build_url(&self, base: &Symbol, quote: &Symbol) -> Result<Url, anyhow::Error> {
-        Url::parse_with_params(
-            &self.provider.base_url,
-            &[
-                ("access_key", self.access_key.clone()),
-                ("base", base.to_string()),
-                ("symbols", quote.to_string()),
-            ],
-        )
-        .context("Failed to build URL")
+    fn supports(&self, symbol: &Symbol) -> bool {
+        // Fiat currencies plus Bitcoin
+        symbol.as_str() == "BTC"
+            || currency::lookup(symbol).map(|c| c.currency_type()) == Some(CurrencyType::Fiat)
+    }
+    fn supports_date(&self, date: &NaiveDate) -> Result<(), ProviderError> {
+        check_date_range(date, NaiveDate::from_ymd_opt(1999, 1, 1).unwrap())
     }
-    async fn get_rate(&self, base: Symbol, quote: Symbol) -> Result<Decimal, anyhow::Error> {
-        let url = self.build_url(&base, &quote)?;
-        let client = reqwest::Client::new();
-        let resp = client.get(url).send().await?.text().await?;
+    fn build_url(
+        &self,
+        base: &Symbol,
+        quote: &Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Url, ProviderError> {
+        self.build_rates_url(base, &[*quote], date)
+    }
+    async fn get_rate(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Decimal, ProviderError> {
+        let url = self.build_url(&base, &quote, date)?;
+        let resp = self.provider.http.get(url, &[]).await?;
+        self.record(&resp);
 
-        let parsed_rate = FixerProvider::parse_rate_from_response(&self, &quote, &resp)?;
+        let parsed_rate = FixerProvider::parse_rate_from_response(&self, &quote, &resp.body)?;
 
         Ok(parsed_rate)
     }
+    async fn get_rates(
+        &self,
+        base: Symbol,
+        quotes: &[Symbol],
+        date: Option<NaiveDate>,
+    ) -> Result<FetchedRates, ProviderError> {
+        let url = self.build_rates_url(&base, quotes, date)?;
+        let resp = self.provider.http.get(url, &[]).await?;
+        self.record(&resp);
+
+        let mut rates = self.parse_rates_from_response(&resp.body)?;
+        rates.retain(|symbol, _| quotes.contains(symbol));
+
+        Ok(rates.into())
+    }
+    async fn get_series(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Series, ProviderError> {
+        let url = self.build_series_url(&base, &quote, from, to)?;
+        let resp = self.provider.http.get(url, &[]).await?;
+        self.record(&resp);
+
+        self.parse_series_from_response(&quote, &resp.body)
+    }
+    fn usage(&self) -> Option<Usage> {
+        Some(self.usage.lock().unwrap().clone())
+    }
     fn parse_rate_from_response(
         &self,
         quote: &Symbol,
         response: &str,
-    ) -> Result<Decimal, anyhow::Error> {
-        serde_json::from_str::<Response>(response)
-            .context("Failed to parse API response")?
-            .rates
+    ) -> Result<Decimal, ProviderError> {
+        self.parse_rates_from_response(response)?
             .get(quote)
             .cloned()
-            .context("Failed to find quote symbol in parsed API response")
+            .ok_or_else(|| {
+                ProviderError::MalformedResponse(anyhow!(
+                    "Failed to find quote symbol in parsed API response"
+                ))
+            })
     }
 }
 
 impl FixerProvider {
-    pub fn new(access_key: String) -> Self {
+    pub fn new(access_key: String, http: Http) -> Self {
         Self {
             provider: BaseProvider {
-                name: String::from("fixer.io"),
-                base_url: String::from("http://data.fixer.io/api/latest"), // FIXME: favor provider that supports https in free plan
+                name: String::from(NAME),
+                base_url: String::from("http://data.fixer.io/api"), // FIXME: favor provider that supports https in free plan
+                http,
             },
             access_key,
+            usage: Mutex::default(),
         }
     }
+
+    /// Counts a request against the plan, which limits requests per month.
+    fn record(&self, response: &HttpResponse) {
+        self.usage.lock().unwrap().record(1, response.quota, None);
+    }
+
+    fn build_rates_url(
+        &self,
+        base: &Symbol,
+        quotes: &[Symbol],
+        date: Option<NaiveDate>,
+    ) -> Result<Url, ProviderError> {
+        let endpoint = date
+            .map(|d| d.to_string())
+            .unwrap_or_else(|| String::from("latest"));
+        let symbols = quotes
+            .iter()
+            .map(|q| q.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Url::parse_with_params(
+            &format!("{}/{}", self.provider.base_url, endpoint),
+            &[
+                ("access_key", self.access_key.clone()),
+                ("base", base.to_string()),
+                ("symbols", symbols),
+            ],
+        )
+        .context("Failed to build URL")
+        .map_err(ProviderError::Other)
+    }
+
+    fn parse_rates_from_response(&self, response: &str) -> Result<Rates, ProviderError> {
+        check_error(response)?;
+        Ok(serde_json::from_str::<Response>(response)?.rates)
+    }
+
+    fn build_series_url(
+        &self,
+        base: &Symbol,
+        quote: &Symbol,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Url, ProviderError> {
+        Url::parse_with_params(
+            &format!("{}/timeseries", self.provider.base_url),
+            &[
+                ("access_key", self.access_key.clone()),
+                ("start_date", from.to_string()),
+                ("end_date", to.to_string()),
+                ("base", base.to_string()),
+                ("symbols", quote.to_string()),
+            ],
+        )
+        .context("Failed to build URL")
+        .map_err(ProviderError::Other)
+    }
+
+    fn parse_series_from_response(
+        &self,
+        quote: &Symbol,
+        response: &str,
+    ) -> Result<Series, ProviderError> {
+        check_error(response)?;
+        serde_json::from_str::<TimeseriesResponse>(response)?
+            .rates
+            .into_iter()
+            .map(|(date, rates)| {
+                rates.get(quote).map(|rate| (date, *rate)).ok_or_else(|| {
+                    ProviderError::MalformedResponse(anyhow!(
+                        "Failed to find quote symbol for {}",
+                        date
+                    ))
+                })
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::FixerProvider;
     use crate::currency::Symbol;
+    use crate::providers::error::ProviderError;
+    use crate::providers::http::Http;
     use crate::providers::provider::Provider;
 
+    use chrono::NaiveDate;
     use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    #[test]
+    fn parses_timeseries_response() {
+        let quote = Symbol::from_str("USD").unwrap();
+        let response = r#"
+            {
+                "success": true,
+                "timeseries": true,
+                "start_date": "2020-01-02",
+                "end_date": "2020-01-03",
+                "base": "EUR",
+                "rates": {
+                    "2020-01-02": { "USD": "1.1193" },
+                    "2020-01-03": { "USD": "1.1147" }
+                }
+            }
+        "#;
+        let provider = FixerProvider::new(String::from("some-access-key"), Http::default());
+
+        let series = provider
+            .parse_series_from_response(&quote, response)
+            .unwrap();
+
+        assert_eq!(series.len(), 2);
+        assert_eq!(
+            series.get(&NaiveDate::from_ymd_opt(2020, 1, 3).unwrap()),
+            Some(&Decimal::new(11147, 4))
+        );
+    }
 
     #[test]
     fn parses_response_correctly() {
-        let quote = Symbol::USD;
+        let quote = Symbol::from_str("USD").unwrap();
         let expected_rate = Decimal::new(111, 4);
         let response = format!(
             r#"
@@ -99,7 +320,7 @@ mod tests {
         "#,
             expected_rate
         );
-        let provider = FixerProvider::new(String::from("some-access-key"));
+        let provider = FixerProvider::new(String::from("some-access-key"), Http::default());
 
         // Note: Converting to Option to get rid of E in Result<T,E>. Otherwise,
         // the assertion fails as anyhow::Error does not implement Eq.
@@ -110,7 +331,7 @@ mod tests {
 
     #[test]
     fn fails_parsing_invalid_response() {
-        let quote = Symbol::USD;
+        let quote = Symbol::from_str("USD").unwrap();
         let response = r#"
             {{
                 "rates": {{
@@ -122,12 +343,33 @@ mod tests {
                 "success": true
             }}
         "#;
-        let provider = FixerProvider::new(String::from("some-access-key"));
+        let provider = FixerProvider::new(String::from("some-access-key"), Http::default());
 
         // Note: Converting to Option to get rid of E in Result<T,E>. Otherwise,
         // the assertion fails as anyhow::Error does not implement Eq.
-        let rate = provider.parse_rate_from_response(&quote, &response);
+        let rate = provider.parse_rate_from_response(&quote, response);
 
         assert!(rate.is_err(), "Parsing invalid response should fail");
     }
+
+    #[test]
+    fn maps_error_responses() {
+        let quote = Symbol::from_str("USD").unwrap();
+        let response = |code: i64| {
+            format!(
+                r#"{{"success": false, "error": {{"code": {}, "type": "some_error", "info": "Some info."}}}}"#,
+                code
+            )
+        };
+        let provider = FixerProvider::new(String::from("some-access-key"), Http::default());
+        let parse = |code| provider.parse_rate_from_response(&quote, &response(code));
+
+        assert!(matches!(parse(101), Err(ProviderError::InvalidApiKey(m)) if m == "Some info."));
+        assert!(matches!(parse(104), Err(ProviderError::RateLimited(_))));
+        assert!(matches!(parse(105), Err(ProviderError::Unsupported(_))));
+        assert!(matches!(
+            parse(404),
+            Err(ProviderError::Api { code: 404, .. })
+        ));
+    }
 }
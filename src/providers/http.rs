@@ -0,0 +1,567 @@
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use native_tls::{Certificate, Protocol, TlsConnector};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, Proxy, StatusCode, Url};
+use tokio::time::{delay_for, timeout};
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::config::NetworkConfig;
+use crate::providers::error::ProviderError;
+use crate::providers::provider::Quota;
+
+/// User agent unless configured otherwise
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// Time a single request may take unless configured otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Retries of transiently failing requests unless configured otherwise
+pub const DEFAULT_RETRIES: u32 = 2;
+/// Wait before the first retry, doubled for every further one
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Longest wait before a retry. Servers asking to wait longer are not retried.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Error of a request or a group of requests that did not finish in time.
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Timed out after {:.1}s", self.0.as_secs_f64())
+    }
+}
+
+impl Error for TimedOut {}
+
+/// Error of a response whose status is worth retrying.
+#[derive(Debug)]
+pub struct BadStatus(pub StatusCode);
+
+impl fmt::Display for BadStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Server responded with {}", self.0)
+    }
+}
+
+impl Error for BadStatus {}
+
+/// Whether `error` or any of its causes is a timeout.
+pub fn is_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| e.is::<TimedOut>())
+}
+
+/// Resolves to the output of `future`, or fails with `TimedOut` once `deadline` passed.
+/// Dropping the future cancels its pending requests.
+pub async fn with_deadline<T, E: Into<anyhow::Error>>(
+    deadline: Option<Duration>,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, anyhow::Error> {
+    match deadline {
+        Some(deadline) => match timeout(deadline, future).await {
+            Ok(result) => result.map_err(Into::into),
+            Err(_) => Err(anyhow!(TimedOut(deadline))),
+        },
+        None => future.await.map_err(Into::into),
+    }
+}
+
+/// Body of a response, along with the quota the server announced in its headers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpResponse {
+    pub body: String,
+    pub quota: Option<Quota>,
+}
+
+/// The outcome of a single attempt of a request.
+enum Attempt {
+    Done(HttpResponse),
+    /// A transient failure, along with the wait the server asked for
+    Retry(anyhow::Error, Option<Duration>),
+    Fail(anyhow::Error),
+}
+
+/// Sends the requests of a provider. Requests taking longer than the timeout are given
+/// up, and requests failing transiently are retried with exponential backoff.
+#[derive(Clone, Debug)]
+pub struct Http {
+    client: Client,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Http::new(Client::new(), DEFAULT_TIMEOUT, DEFAULT_RETRIES)
+    }
+}
+
+impl Http {
+    pub fn new(client: Client, timeout: Duration, retries: u32) -> Self {
+        Http {
+            client,
+            timeout,
+            retries,
+        }
+    }
+
+    /// Fetches the body of `url`. Error responses that are not worth retrying are
+    /// returned as well, since APIs explain their errors in the body.
+    pub async fn get(
+        &self,
+        url: Url,
+        headers: &[(&str, &str)],
+    ) -> Result<HttpResponse, ProviderError> {
+        let mut attempt = 0;
+        loop {
+            let (error, requested) = match timeout(self.timeout, self.attempt(&url, headers)).await
+            {
+                Ok(Attempt::Done(response)) => return Ok(response),
+                Ok(Attempt::Fail(error)) => return Err(ProviderError::Network(error)),
+                Ok(Attempt::Retry(error, requested)) => (error, requested),
+                Err(_) => (anyhow!(TimedOut(self.timeout)), None),
+            };
+
+            match backoff(attempt, requested) {
+                Some(wait) if attempt < self.retries => delay_for(wait).await,
+                _ => return Err(give_up(error, attempt + 1)),
+            }
+            attempt += 1;
+        }
+    }
+
+    async fn attempt(&self, url: &Url, headers: &[(&str, &str)]) -> Attempt {
+        let request = headers
+            .iter()
+            .fold(self.client.get(url.clone()), |r, (name, value)| {
+                r.header(*name, *value)
+            });
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) if is_transient(&e) => return Attempt::Retry(e.into(), None),
+            Err(e) => return Attempt::Fail(e.into()),
+        };
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            let requested = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| parse_retry_after(v, Utc::now()));
+            return Attempt::Retry(anyhow!(BadStatus(status)), requested);
+        }
+
+        let quota = parse_quota(response.headers());
+        match response.text().await {
+            Ok(body) => Attempt::Done(HttpResponse { body, quota }),
+            Err(e) if is_transient(&e) => Attempt::Retry(e.into(), None),
+            Err(e) => Attempt::Fail(e.into()),
+        }
+    }
+}
+
+/// The error of a request given up after `attempts`. Servers still refusing requests for
+/// their number are rate limiting.
+fn give_up(error: anyhow::Error, attempts: u32) -> ProviderError {
+    let rate_limited = matches!(
+        error.downcast_ref::<BadStatus>(),
+        Some(BadStatus(StatusCode::TOO_MANY_REQUESTS))
+    );
+    let error = match attempts {
+        1 => error,
+        _ => error.context(format!("Failed after {} attempts", attempts)),
+    };
+
+    match rate_limited {
+        true => ProviderError::RateLimited(format!("{:#}", error)),
+        _ => ProviderError::Network(error),
+    }
+}
+
+/// Builds the client whose connections are shared by all providers, so that every
+/// request honors the proxy, certificates and TLS settings of the config.
+pub fn build_client(config: &NetworkConfig) -> Result<Client, anyhow::Error> {
+    let mut builder =
+        Client::builder().user_agent(config.user_agent.as_deref().unwrap_or(USER_AGENT));
+
+    // Proxies of the environment are only picked up by the client as long as no proxy
+    // is set up, so they are set up here if hosts have to bypass them
+    let proxies = match &config.proxy {
+        Some(proxy) => {
+            let url =
+                Url::parse(proxy).with_context(|| format!("Invalid proxy URL '{}'", proxy))?;
+            vec![("http", url.clone()), ("https", url)]
+        }
+        None if !config.no_proxy.is_empty() => env_proxies(|name| env::var(name).ok())?,
+        None => Vec::new(),
+    };
+    if !proxies.is_empty() {
+        let no_proxy = config.no_proxy.clone();
+        builder = builder.proxy(Proxy::custom(move |target| {
+            select_proxy(target, &proxies, &no_proxy)
+        }));
+    }
+
+    if config.ca_bundle.is_some() || config.tls_min_version.is_some() {
+        let mut tls = TlsConnector::builder();
+        if let Some(version) = &config.tls_min_version {
+            tls.min_protocol_version(Some(parse_tls_version(version)?));
+        }
+        if let Some(path) = &config.ca_bundle {
+            let bundle = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read CA bundle {}", path.display()))?;
+            let certificates = split_pem(&bundle);
+            if certificates.is_empty() {
+                return Err(anyhow!("No certificates found in {}", path.display()));
+            }
+            for pem in certificates {
+                let certificate = Certificate::from_pem(pem.as_bytes())
+                    .with_context(|| format!("Invalid certificate in {}", path.display()))?;
+                tls.add_root_certificate(certificate);
+            }
+        }
+        builder = builder.use_preconfigured_tls(tls.build().context("Failed to set up TLS")?);
+    }
+
+    builder.build().context("Failed to set up HTTP client")
+}
+
+/// Reads the proxies of `HTTP_PROXY` and `HTTPS_PROXY`, or their lowercase variants,
+/// along with the scheme of the URLs they are used for.
+fn env_proxies(
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<(&'static str, Url)>, anyhow::Error> {
+    [("http", "HTTP_PROXY"), ("https", "HTTPS_PROXY")]
+        .iter()
+        .filter_map(|(scheme, name)| {
+            let proxy = var(name)
+                .or_else(|| var(&name.to_lowercase()))
+                .filter(|p| !p.trim().is_empty())?;
+            Some(
+                Url::parse(proxy.trim())
+                    .with_context(|| format!("Invalid proxy URL '{}' in {}", proxy, name))
+                    .map(|url| (*scheme, url)),
+            )
+        })
+        .collect()
+}
+
+/// The proxy of the scheme of `url`, unless its host is reached directly.
+fn select_proxy(url: &Url, proxies: &[(&str, Url)], no_proxy: &[String]) -> Option<Url> {
+    if bypasses_proxy(url, no_proxy) {
+        return None;
+    }
+    proxies
+        .iter()
+        .find(|(scheme, _)| *scheme == url.scheme())
+        .map(|(_, proxy)| proxy.clone())
+}
+
+/// Whether `url` is reached directly, given hosts like `example.com`, which matches its
+/// subdomains as well, `.example.com`, which only matches subdomains, or `*`.
+fn bypasses_proxy(url: &Url, no_proxy: &[String]) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host.to_lowercase(),
+        None => return false,
+    };
+
+    no_proxy
+        .iter()
+        .map(|e| e.trim().to_lowercase())
+        .any(|entry| match entry.strip_prefix('.') {
+            _ if entry == "*" => true,
+            Some(domain) => host.ends_with(&format!(".{}", domain)),
+            None => host == entry || host.ends_with(&format!(".{}", entry)),
+        })
+}
+
+fn parse_tls_version(version: &str) -> Result<Protocol, anyhow::Error> {
+    match version.trim() {
+        "1.0" => Ok(Protocol::Tlsv10),
+        "1.1" => Ok(Protocol::Tlsv11),
+        "1.2" => Ok(Protocol::Tlsv12),
+        "1.3" => Ok(Protocol::Tlsv13),
+        _ => Err(anyhow!(
+            "Unknown TLS version '{}', expected one of 1.0, 1.1, 1.2 or 1.3",
+            version
+        )),
+    }
+}
+
+/// Splits a bundle into its PEM encoded certificates.
+fn split_pem(bundle: &str) -> Vec<&str> {
+    let mut certificates = Vec::new();
+    let mut rest = bundle;
+    while let Some(start) = rest.find(PEM_BEGIN) {
+        match rest[start..].find(PEM_END) {
+            Some(end) => {
+                let end = start + end + PEM_END.len();
+                certificates.push(&rest[start..end]);
+                rest = &rest[end..];
+            }
+            None => break,
+        }
+    }
+    certificates
+}
+
+/// Failures to connect or to transfer a response, as opposed to invalid requests.
+fn is_transient(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect() || error.is_request() || error.is_body()
+}
+
+/// Wait before retrying after the failed `attempt`, counting from 0. A wait requested
+/// by the server is honored unless it is too long.
+fn backoff(attempt: u32, requested: Option<Duration>) -> Option<Duration> {
+    match requested {
+        Some(wait) if wait > MAX_BACKOFF => None,
+        Some(wait) => Some(wait),
+        None => Some(
+            INITIAL_BACKOFF
+                .checked_mul(2u32.saturating_pow(attempt))
+                .map_or(MAX_BACKOFF, |wait| wait.min(MAX_BACKOFF)),
+        ),
+    }
+}
+
+/// Reads the lowest quota among headers like `X-RateLimit-Remaining-Month` and
+/// `X-RateLimit-Limit-Month`, which plans limiting requests per month or day send.
+fn parse_quota(headers: &HeaderMap) -> Option<Quota> {
+    ["-month", "-day", ""]
+        .iter()
+        .filter_map(|period| {
+            let value = |name: &str| {
+                headers
+                    .get(format!("x-ratelimit-{}{}", name, period).as_str())?
+                    .to_str()
+                    .ok()?
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            };
+            Some(Quota {
+                remaining: value("remaining")?,
+                limit: value("limit"),
+            })
+        })
+        .min()
+}
+
+/// Reads a Retry-After header, which holds either seconds or an HTTP date.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    DateTime::parse_from_rfc2822(value.trim()).ok().map(|date| {
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        backoff, build_client, bypasses_proxy, env_proxies, give_up, is_timeout, parse_quota,
+        parse_retry_after, parse_tls_version, select_proxy, split_pem, with_deadline, BadStatus,
+        TimedOut, MAX_BACKOFF,
+    };
+    use crate::config::NetworkConfig;
+    use crate::providers::error::ProviderError;
+    use crate::providers::provider::Quota;
+
+    use anyhow::anyhow;
+    use chrono::{TimeZone, Utc};
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use reqwest::{StatusCode, Url};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn url(input: &str) -> Url {
+        Url::parse(input).unwrap()
+    }
+
+    #[test]
+    fn doubles_backoff_up_to_limit() {
+        assert_eq!(backoff(0, None), Some(Duration::from_millis(500)));
+        assert_eq!(backoff(2, None), Some(Duration::from_secs(2)));
+        assert_eq!(backoff(40, None), Some(MAX_BACKOFF));
+    }
+
+    #[test]
+    fn honors_requested_wait() {
+        assert_eq!(
+            backoff(0, Some(Duration::from_secs(3))),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(backoff(0, Some(Duration::from_secs(3600))), None);
+    }
+
+    #[test]
+    fn parses_retry_after_in_seconds_and_dates() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 27, 30).unwrap();
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn parses_lowest_quota_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_quota(&headers), None);
+
+        headers.insert("X-RateLimit-Limit-Month", HeaderValue::from_static("1000"));
+        headers.insert(
+            "X-RateLimit-Remaining-Month",
+            HeaderValue::from_static("940"),
+        );
+        headers.insert("X-RateLimit-Remaining-Day", HeaderValue::from_static("12"));
+        headers.insert("X-RateLimit-Remaining", HeaderValue::from_static("many"));
+
+        assert_eq!(
+            parse_quota(&headers),
+            Some(Quota {
+                remaining: 12,
+                limit: None
+            })
+        );
+    }
+
+    #[test]
+    fn detects_timeouts_among_causes() {
+        let error = anyhow!(TimedOut(Duration::from_secs(1))).context("Failed to fetch");
+
+        assert!(is_timeout(&error));
+        assert!(!is_timeout(&anyhow!("Failed to fetch")));
+    }
+
+    #[test]
+    fn gives_up_rate_limited_requests() {
+        let rate_limited = give_up(anyhow!(BadStatus(StatusCode::TOO_MANY_REQUESTS)), 3);
+        let unavailable = give_up(anyhow!(BadStatus(StatusCode::SERVICE_UNAVAILABLE)), 1);
+
+        assert_eq!(
+            rate_limited.to_string(),
+            "Rate limited: Failed after 3 attempts: Server responded with 429 Too Many Requests"
+        );
+        assert!(matches!(unavailable, ProviderError::Network(_)));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_deadline() {
+        let pending = futures::future::pending::<Result<(), anyhow::Error>>();
+        let result = with_deadline(Some(Duration::from_millis(10)), pending).await;
+
+        assert!(is_timeout(&result.unwrap_err()));
+        assert!(with_deadline(None, async { Ok::<_, anyhow::Error>(()) })
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn bypasses_environment_proxies_for_listed_hosts() {
+        let var = |name: &str| match name {
+            "HTTP_PROXY" => Some(String::from("http://plain.example.com:3128")),
+            "https_proxy" => Some(String::from("http://secure.example.com:3128")),
+            _ => None,
+        };
+        let proxies = env_proxies(var).unwrap();
+        let no_proxy = vec![String::from("internal.example.com")];
+
+        assert_eq!(
+            select_proxy(&url("https://api.example.com/latest"), &proxies, &no_proxy),
+            Some(url("http://secure.example.com:3128"))
+        );
+        assert_eq!(
+            select_proxy(&url("http://api.example.com/latest"), &proxies, &no_proxy),
+            Some(url("http://plain.example.com:3128"))
+        );
+        assert_eq!(
+            select_proxy(&url("https://internal.example.com/"), &proxies, &no_proxy),
+            None
+        );
+        assert!(env_proxies(|_| Some(String::from("not a url"))).is_err());
+    }
+
+    #[test]
+    fn bypasses_proxy_for_listed_hosts_and_subdomains() {
+        let no_proxy = vec![String::from("example.com"), String::from(".internal")];
+
+        assert!(bypasses_proxy(&url("https://example.com/a"), &no_proxy));
+        assert!(bypasses_proxy(&url("https://api.EXAMPLE.com"), &no_proxy));
+        assert!(bypasses_proxy(&url("http://rates.internal"), &no_proxy));
+        assert!(!bypasses_proxy(&url("http://internal"), &no_proxy));
+        assert!(!bypasses_proxy(&url("https://notexample.com"), &no_proxy));
+        assert!(bypasses_proxy(
+            &url("https://any.host"),
+            &[String::from("*")]
+        ));
+    }
+
+    #[test]
+    fn splits_certificate_bundle() {
+        let bundle = "# root\n-----BEGIN CERTIFICATE-----\nAAA\n-----END CERTIFICATE-----\n\
+                      -----BEGIN CERTIFICATE-----\nBBB\n-----END CERTIFICATE-----\n";
+
+        let certificates = split_pem(bundle);
+
+        assert_eq!(certificates.len(), 2);
+        assert!(certificates[1].contains("BBB"));
+        assert!(split_pem("no certificates").is_empty());
+    }
+
+    #[test]
+    fn parses_tls_versions() {
+        assert!(parse_tls_version("1.2").is_ok());
+        assert!(parse_tls_version("TLS 9").is_err());
+    }
+
+    #[test]
+    fn builds_client_from_config() {
+        let config = NetworkConfig {
+            proxy: Some(String::from("http://proxy.example.com:3128")),
+            no_proxy: vec![String::from("localhost")],
+            user_agent: Some(String::from("invoicing/1.0")),
+            tls_min_version: Some(String::from("1.2")),
+            ..NetworkConfig::default()
+        };
+
+        assert!(build_client(&config).is_ok());
+    }
+
+    #[test]
+    fn fails_on_invalid_network_settings() {
+        let invalid_proxy = NetworkConfig {
+            proxy: Some(String::from("not a url")),
+            ..NetworkConfig::default()
+        };
+        let missing_bundle = NetworkConfig {
+            ca_bundle: Some(PathBuf::from("/nonexistent/ca.pem")),
+            ..NetworkConfig::default()
+        };
+
+        assert!(build_client(&invalid_proxy).is_err());
+        assert!(build_client(&missing_bundle).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+pub mod error;
+pub(crate) mod http;
 pub mod provider;
 
 pub mod coinmarketcap;
@@ -1,23 +1,215 @@
 use async_trait::async_trait;
+use chrono::{Local, NaiveDate};
+use futures::future::join_all;
 use reqwest::Url;
 use rust_decimal::Decimal;
 
+use std::collections::{BTreeMap, HashMap};
+
 use crate::currency::Symbol;
+use crate::providers::error::ProviderError;
+use crate::providers::http::Http;
+
+/// Rates of several quotes against the same base
+pub type Rates = HashMap<Symbol, Decimal>;
+
+/// Rates fetched for several quotes against the same base. Quotes missing from `rates`
+/// could not be fetched, `error` tells why for the first of them if known.
+#[derive(Debug, Default)]
+pub struct FetchedRates {
+    pub rates: Rates,
+    pub error: Option<ProviderError>,
+}
+
+impl From<Rates> for FetchedRates {
+    fn from(rates: Rates) -> Self {
+        FetchedRates { rates, error: None }
+    }
+}
+
+/// Daily rates, ordered by date
+pub type Series = BTreeMap<NaiveDate, Decimal>;
+
+/// Requests left on the plan of a provider, as announced by its API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Quota {
+    pub remaining: u64,
+    pub limit: Option<u64>,
+}
+
+/// Usage of the plan of a provider since it was set up.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Usage {
+    /// Requests, or credits for providers charging several per request
+    pub credits: u64,
+    /// Lowest quota announced
+    pub quota: Option<Quota>,
+    /// Latest notice of the API, e.g. that the plan is about to run out
+    pub notice: Option<String>,
+}
+
+impl Usage {
+    pub fn record(&mut self, credits: u64, quota: Option<Quota>, notice: Option<String>) {
+        self.credits += credits;
+        self.quota = self.quota.into_iter().chain(quota).min();
+        if notice.is_some() {
+            self.notice = notice;
+        }
+    }
+
+    /// Whether less than a tenth of the quota is left, or the API warned about it.
+    pub fn is_running_out(&self) -> bool {
+        let low = match self.quota {
+            Some(Quota {
+                remaining,
+                limit: Some(limit),
+            }) => remaining < limit / 10,
+            Some(Quota { remaining, .. }) => remaining == 0,
+            None => false,
+        };
+        low || self.notice.is_some()
+    }
+}
 
 pub struct BaseProvider {
     #[allow(dead_code)]
     pub name: String,
     pub base_url: String,
+    pub http: Http,
 }
 
 #[async_trait]
-pub(crate) trait Provider {
+pub trait Provider: Send + Sync {
     fn get_name(&self) -> String;
-    fn build_url(&self, base: &Symbol, quote: &Symbol) -> Result<Url, anyhow::Error>;
+    fn supports(&self, symbol: &Symbol) -> bool;
+    fn supports_pair(&self, base: &Symbol, quote: &Symbol) -> bool {
+        self.supports(base) && self.supports(quote)
+    }
+    /// Fails with the reason if rates of `date` cannot be served.
+    fn supports_date(&self, _date: &NaiveDate) -> Result<(), ProviderError> {
+        Err(ProviderError::Unsupported(String::from(
+            "Historical rates are not supported",
+        )))
+    }
+    fn build_url(
+        &self,
+        base: &Symbol,
+        quote: &Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Url, ProviderError>;
     fn parse_rate_from_response(
         &self,
         quote: &Symbol,
         response: &str,
-    ) -> Result<Decimal, anyhow::Error>;
-    async fn get_rate(&self, base: Symbol, quote: Symbol) -> Result<Decimal, anyhow::Error>;
+    ) -> Result<Decimal, ProviderError>;
+    /// Usage of the plan, for providers charging requests against one.
+    fn usage(&self) -> Option<Usage> {
+        None
+    }
+    /// Fetches the latest rate, or the rate of `date` if given.
+    async fn get_rate(
+        &self,
+        base: Symbol,
+        quote: Symbol,
+        date: Option<NaiveDate>,
+    ) -> Result<Decimal, ProviderError>;
+    /// Fetches the rates of several quotes, failing only if none could be fetched.
+    /// Providers whose API accepts several symbols per request override this to save
+    /// requests.
+    async fn get_rates(
+        &self,
+        base: Symbol,
+        quotes: &[Symbol],
+        date: Option<NaiveDate>,
+    ) -> Result<FetchedRates, ProviderError> {
+        let results = join_all(quotes.iter().map(|q| self.get_rate(base, *q, date))).await;
+
+        let mut rates = Rates::new();
+        let mut error = None;
+        for (quote, result) in quotes.iter().zip(results) {
+            match result {
+                Ok(rate) => {
+                    rates.insert(*quote, rate);
+                }
+                Err(e) => error = error.or(Some(e)),
+            }
+        }
+
+        match (rates.is_empty(), error) {
+            (true, Some(e)) => Err(e),
+            (_, error) => Ok(FetchedRates { rates, error }),
+        }
+    }
+    /// Fetches the daily rates from `from` to `to`, both inclusive.
+    async fn get_series(
+        &self,
+        _base: Symbol,
+        _quote: Symbol,
+        _from: NaiveDate,
+        _to: NaiveDate,
+    ) -> Result<Series, ProviderError> {
+        Err(ProviderError::Unsupported(String::from(
+            "Time series are not supported",
+        )))
+    }
+}
+
+/// Checks that `date` lies between the first day a provider has data for and today.
+pub fn check_date_range(date: &NaiveDate, first: NaiveDate) -> Result<(), ProviderError> {
+    if *date < first {
+        Err(ProviderError::Unsupported(format!(
+            "Historical rates are only available from {}",
+            first
+        )))
+    } else if *date > Local::now().date_naive() {
+        Err(ProviderError::Unsupported(format!(
+            "Rates of {} are not known yet",
+            date
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Quota, Usage};
+
+    #[test]
+    fn records_lowest_quota_and_latest_notice() {
+        let quota = |remaining| {
+            Some(Quota {
+                remaining,
+                limit: Some(1000),
+            })
+        };
+        let mut usage = Usage::default();
+
+        usage.record(1, quota(120), None);
+        usage.record(2, quota(150), None);
+        assert_eq!(usage.credits, 3);
+        assert_eq!(usage.quota, quota(120));
+        assert!(!usage.is_running_out());
+
+        usage.record(1, quota(99), Some(String::from("Almost used up")));
+        usage.record(1, None, None);
+        assert_eq!(usage.quota, quota(99));
+        assert_eq!(usage.notice.as_deref(), Some("Almost used up"));
+        assert!(usage.is_running_out());
+    }
+
+    #[test]
+    fn handles_huge_quotas() {
+        let mut usage = Usage::default();
+        usage.record(
+            1,
+            Some(Quota {
+                remaining: u64::MAX,
+                limit: Some(u64::MAX),
+            }),
+            None,
+        );
+
+        assert!(!usage.is_running_out());
+    }
 }
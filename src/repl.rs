@@ -0,0 +1,172 @@
+use anyhow::Context;
+use colored::*;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use currency_converter::cache::RateCache;
+use currency_converter::Converter;
+
+use crate::cli::{is_expression_query, parse_expression_query, parse_query};
+use crate::convert::{convert, evaluate, print_guidance, Options};
+
+const HISTORY_DIR: &str = "currency-converter";
+const HISTORY_FILE: &str = "history.txt";
+const PROMPT: &str = "> ";
+
+const HELP: &str = "Enter conversions like '100 eur in usd, gbp', '5 usd in eur on 2020-03-15' or
+'120 usd + 35 eur - 10 gbp in tl'.
+
+Commands:
+  :stats      Toggle conversion statistics
+  :providers  List providers and their cached rates
+  :refresh    Forget the rates of this session, so they are looked up again
+  :help       Show this help
+  :quit       Leave, like Ctrl-D";
+
+/// A command entered at the prompt.
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    Stats,
+    Providers,
+    Refresh,
+    Help,
+    Quit,
+    Unknown(&'a str),
+    Convert(Vec<&'a str>),
+    Empty,
+}
+
+impl<'a> From<&'a str> for Command<'a> {
+    fn from(line: &'a str) -> Self {
+        match line.trim() {
+            "" => Command::Empty,
+            ":stats" | ":s" => Command::Stats,
+            ":providers" | ":p" => Command::Providers,
+            ":refresh" | ":r" => Command::Refresh,
+            ":help" | ":h" | "?" => Command::Help,
+            ":quit" | ":q" | ":exit" => Command::Quit,
+            command if command.starts_with(':') => Command::Unknown(command),
+            query => Command::Convert(query.split_whitespace().collect()),
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join(HISTORY_DIR).join(HISTORY_FILE))
+}
+
+/// Answers conversions typed at a prompt until `:quit` or the end of input. The
+/// converter and the rates of its session are kept until the end.
+pub async fn run(
+    mut options: Options,
+    converter: Converter,
+    cache: Arc<Mutex<RateCache>>,
+) -> Result<(), anyhow::Error> {
+    let mut editor = Editor::<()>::new();
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history is expected on first use
+        let _ = editor.load_history(path);
+    }
+
+    println!("{}", "Type ':help' for help, ':quit' to leave.".dimmed());
+
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e).context("Failed to read input"),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.trim());
+        }
+
+        match Command::from(line.as_str()) {
+            Command::Empty => {}
+            Command::Quit => break,
+            Command::Help => println!("{}", HELP),
+            Command::Stats => {
+                options.stats = !options.stats;
+                println!("Statistics {}", if options.stats { "on" } else { "off" });
+            }
+            Command::Providers => {
+                let cache = cache.lock().unwrap();
+                match converter.is_offline() {
+                    true => println!("  cache: {} rates (offline)", cache.len()),
+                    _ => converter.providers().iter().for_each(|p| {
+                        println!(
+                            "  {}: {} rates cached",
+                            p.get_name(),
+                            cache.count(&p.get_name())
+                        )
+                    }),
+                }
+            }
+            Command::Refresh => match converter.is_offline() {
+                true => eprintln!("{}", "Cannot refresh rates offline".red()),
+                _ => {
+                    if let Some(session) = converter.session() {
+                        session.lock().unwrap().clear();
+                    }
+                    println!("Rates will be looked up again");
+                }
+            },
+            Command::Unknown(command) => {
+                let message = format!("Unknown command {}, see :help", command);
+                eprintln!("{}", message.red());
+            }
+            Command::Convert(words) => {
+                let result = match is_expression_query(&words) {
+                    true => match parse_expression_query(words) {
+                        Ok(query) => evaluate(&options, &converter, &query).await,
+                        Err(e) => Err(e),
+                    },
+                    _ => match parse_query(words) {
+                        Ok(query) => convert(&options, &converter, &query).await,
+                        Err(e) => Err(e),
+                    },
+                };
+                if let Err(e) = result {
+                    eprintln!("{}", format!("Error: {:#}", e).red());
+                    print_guidance(&e);
+                }
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Warning: Failed to save history: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::from(":stats"), Command::Stats);
+        assert_eq!(Command::from(" :q "), Command::Quit);
+        assert_eq!(Command::from(":foo"), Command::Unknown(":foo"));
+        assert_eq!(Command::from("  "), Command::Empty);
+    }
+
+    #[test]
+    fn parses_conversions() {
+        assert_eq!(
+            Command::from("100 eur in usd"),
+            Command::Convert(vec!["100", "eur", "in", "usd"])
+        );
+    }
+}
@@ -0,0 +1,813 @@
+use anyhow::{anyhow, Context};
+use chrono::NaiveDate;
+use futures::future::try_join_all;
+use futures::StreamExt;
+use rust_decimal::Decimal;
+use tokio::sync::Semaphore;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::currency::Symbol;
+use crate::progress::Progress;
+use crate::progress_join::{completions, Dropped};
+use crate::providers::http::with_deadline;
+use crate::providers::provider::Provider;
+
+// Upper bounds that keep the number of requests per conversion reasonable.
+pub const MAX_LEGS: usize = 3;
+pub const MAX_ROUTES: usize = 8;
+pub const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// A single conversion step served by one provider.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Leg {
+    pub provider: usize,
+    pub base: Symbol,
+    pub quote: Symbol,
+}
+
+/// A chain of legs leading from a base to a quote currency. The rate of a route is
+/// the product of the rates of its legs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Route {
+    pub legs: Vec<Leg>,
+}
+
+/// How long fetching waits for pending requests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Wait {
+    /// Time after which pending requests are given up
+    pub deadline: Option<Duration>,
+    /// Number of successful routes per target currency after which pending requests
+    /// are dropped
+    pub quorum: Option<usize>,
+}
+
+/// Fetched rates of legs. Failures are reference counted since a single failed request
+/// can be shared by several legs.
+pub type LegRates = HashMap<Leg, Result<Decimal, LegError>>;
+
+/// Why the rate of a leg could not be fetched.
+#[derive(Clone, Debug)]
+pub struct LegError(Arc<anyhow::Error>);
+
+impl fmt::Display for LegError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for LegError {
+    // Causes are passed on, e.g. to tell timeouts apart
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        AsRef::<dyn Error + Send + Sync>::as_ref(&*self.0).source()
+    }
+}
+
+/// Models every provider's supported pairs as directed edges between currencies.
+pub struct Graph {
+    edges: HashMap<Symbol, Vec<(usize, Symbol)>>,
+}
+
+impl Graph {
+    #[cfg(test)]
+    pub fn new(providers: &[Box<dyn Provider>], symbols: &[Symbol]) -> Self {
+        Graph::with_filter(providers, symbols, |_| true)
+    }
+
+    /// Like `new`, but leaves out providers rejected by `filter`, e.g. providers without
+    /// historical rates of a date.
+    pub fn with_filter(
+        providers: &[Box<dyn Provider>],
+        symbols: &[Symbol],
+        filter: impl Fn(&dyn Provider) -> bool,
+    ) -> Self {
+        let mut edges = HashMap::<Symbol, Vec<(usize, Symbol)>>::new();
+
+        for (i, provider) in providers.iter().enumerate() {
+            if !filter(provider.as_ref()) {
+                continue;
+            }
+
+            let supported = symbols
+                .iter()
+                .filter(|s| provider.supports(s))
+                .collect::<Vec<_>>();
+
+            for base in supported.iter() {
+                for quote in supported.iter().filter(|q| q != &base) {
+                    if provider.supports_pair(base, quote) {
+                        edges.entry(**base).or_default().push((i, **quote));
+                    }
+                }
+            }
+        }
+
+        Graph { edges }
+    }
+
+    /// Finds the shortest routes from `base` to `quote`. All routes with the minimal
+    /// number of legs are returned, so a pair quoted by several providers yields
+    /// one route per provider.
+    pub fn find_routes(&self, base: Symbol, quote: Symbol, max_legs: usize) -> Vec<Route> {
+        let mut routes: Vec<Route> = vec![];
+        let mut queue = VecDeque::new();
+        queue.push_back(Route { legs: vec![] });
+
+        while let Some(route) = queue.pop_front() {
+            // BFS visits routes by increasing length, so once a route was found, longer
+            // candidates can be discarded.
+            if let Some(found) = routes.first() {
+                if route.legs.len() >= found.legs.len() {
+                    break;
+                }
+            }
+            if route.legs.len() >= max_legs {
+                continue;
+            }
+
+            let current = route.legs.last().map(|l| l.quote).unwrap_or(base);
+            for (provider, next) in self.edges.get(&current).into_iter().flatten() {
+                if route.visits(next) || *next == base {
+                    continue;
+                }
+
+                let mut legs = route.legs.clone();
+                legs.push(Leg {
+                    provider: *provider,
+                    base: current,
+                    quote: *next,
+                });
+
+                if *next == quote {
+                    routes.push(Route { legs });
+                } else {
+                    queue.push_back(Route { legs });
+                }
+            }
+        }
+
+        routes.truncate(MAX_ROUTES);
+        routes
+    }
+}
+
+impl Route {
+    fn visits(&self, symbol: &Symbol) -> bool {
+        self.legs.iter().any(|l| l.quote == *symbol)
+    }
+
+    pub async fn get_rate(
+        &self,
+        providers: &[Box<dyn Provider>],
+        date: Option<NaiveDate>,
+    ) -> Result<Decimal, anyhow::Error> {
+        let rates = try_join_all(self.legs.iter().map(|leg| async move {
+            providers[leg.provider]
+                .get_rate(leg.base, leg.quote, date)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to fetch {} -> {} from {}",
+                        leg.base,
+                        leg.quote,
+                        providers[leg.provider].get_name()
+                    )
+                })
+        }))
+        .await?;
+
+        Ok(rates.iter().product())
+    }
+
+    /// Computes the rate of the route from rates fetched by `fetch_legs`.
+    pub fn rate_from(&self, rates: &LegRates) -> Result<Decimal, anyhow::Error> {
+        let rates = self
+            .legs
+            .iter()
+            .map(|leg| match rates.get(leg) {
+                Some(Ok(rate)) => Ok(*rate),
+                Some(Err(e)) => Err(anyhow!(e.clone())),
+                None => Err(anyhow!("No rate fetched for {} -> {}", leg.base, leg.quote)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(rates.iter().product())
+    }
+
+    /// Renders the route like `TRY -(exchangeratesapi.io)-> USD -(coinmarketcap.com)-> ETH`.
+    pub fn describe<'a>(&'a self, providers: &'a [Box<dyn Provider>]) -> RouteDescription<'a> {
+        RouteDescription {
+            route: self,
+            providers,
+        }
+    }
+}
+
+/// Fetches the rates of all legs of the routes of each target currency, along with the
+/// date whose rates the routes need. Legs served by the same provider from the same
+/// base currency on the same date are fetched in a single request, e.g. `EUR -> USD`
+/// and `EUR -> GBP` when converting into several currencies. At most
+/// `MAX_CONCURRENT_REQUESTS` requests are sent at once. Requests still pending after
+/// the deadline are given up. Requests pending once every target currency has the
+/// quorum of complete routes, or no route left that could complete, are dropped,
+/// failing their legs.
+pub async fn fetch_legs(
+    routes: &[(Option<NaiveDate>, &[Route])],
+    providers: &[Box<dyn Provider>],
+    progress: bool,
+    wait: Wait,
+) -> HashMap<Option<NaiveDate>, LegRates> {
+    let mut groups = BTreeMap::<(Option<NaiveDate>, usize, Symbol), Vec<Symbol>>::new();
+    for (date, leg) in routes
+        .iter()
+        .flat_map(|(date, routes)| routes.iter().map(move |r| (*date, r)))
+        .flat_map(|(date, r)| r.legs.iter().map(move |l| (date, l)))
+    {
+        let quotes = groups.entry((date, leg.provider, leg.base)).or_default();
+        if !quotes.contains(&leg.quote) {
+            quotes.push(leg.quote);
+        }
+    }
+
+    let requests = Semaphore::new(MAX_CONCURRENT_REQUESTS);
+    let futures = groups.iter().map(|((date, provider, base), quotes)| {
+        let requests = &requests;
+        async move {
+            let context = || {
+                format!(
+                    "Failed to fetch {} -> {} from {}",
+                    base,
+                    quotes
+                        .iter()
+                        .map(|q| q.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    providers[*provider].get_name()
+                )
+            };
+            // Waiting for other requests counts towards the deadline
+            let fetched = with_deadline(wait.deadline, async {
+                let _permit = requests.acquire().await;
+                providers[*provider].get_rates(*base, quotes, *date).await
+            })
+            .await
+            .with_context(context)?;
+            // Quotes that failed on their own keep the error of the provider
+            let error = fetched.error.map(|e| anyhow!(e).context(context()));
+            Ok((fetched.rates, error))
+        }
+    });
+    let labels = groups
+        .iter()
+        .map(|((date, provider, base), quotes)| {
+            let quotes = quotes.iter().map(|q| q.to_string()).collect::<Vec<_>>();
+            format!(
+                "{} {} → {}{}",
+                providers[*provider].get_name(),
+                base,
+                quotes.join(", "),
+                date.map(|d| format!(" on {}", d)).unwrap_or_default()
+            )
+        })
+        .collect();
+    let indices = groups
+        .keys()
+        .enumerate()
+        .map(|(index, key)| (*key, index))
+        .collect::<HashMap<_, _>>();
+    let mut results = groups.keys().map(|_| None).collect::<Vec<_>>();
+    let mut completions = completions(futures, Progress::new(labels, progress));
+    while let Some((index, result)) = completions.next().await {
+        results[index] = Some(result);
+
+        if let Some(quorum) = wait.quorum {
+            let fetched = |date: Option<NaiveDate>, leg: &Leg| match &results
+                [indices[&(date, leg.provider, leg.base)]]
+            {
+                Some(Ok((rates, _))) => Some(rates.contains_key(&leg.quote)),
+                Some(Err(_)) => Some(false),
+                None => None,
+            };
+            if reached_quorum(routes, quorum, fetched) {
+                break;
+            }
+        }
+    }
+    drop(completions);
+
+    let mut rates = routes
+        .iter()
+        .map(|(date, _)| (*date, LegRates::new()))
+        .collect::<HashMap<_, _>>();
+    for (((date, provider, base), quotes), result) in groups.into_iter().zip(results) {
+        let result = result
+            .unwrap_or_else(|| Err(anyhow!(Dropped)))
+            .map_err(|e| LegError(Arc::new(e)));
+        let result = result.map(|(rates, error)| (rates, error.map(|e| LegError(Arc::new(e)))));
+        let rates = rates.entry(date).or_default();
+        for quote in quotes {
+            let rate = match &result {
+                Ok((fetched, error)) => fetched.get(&quote).cloned().ok_or_else(|| match error {
+                    Some(e) => e.clone(),
+                    None => LegError(Arc::new(anyhow!(
+                        "{} did not return a rate for {} -> {}",
+                        providers[provider].get_name(),
+                        base,
+                        quote
+                    ))),
+                }),
+                Err(e) => Err(e.clone()),
+            };
+            rates.insert(
+                Leg {
+                    provider,
+                    base,
+                    quote,
+                },
+                rate,
+            );
+        }
+    }
+
+    rates
+}
+
+/// Whether each target currency has `quorum` routes whose legs were all fetched, or no
+/// route left that could still complete. `fetched` tells whether the rate of a leg on
+/// a date was fetched, or `None` while it is pending.
+fn reached_quorum(
+    routes: &[(Option<NaiveDate>, &[Route])],
+    quorum: usize,
+    fetched: impl Fn(Option<NaiveDate>, &Leg) -> Option<bool>,
+) -> bool {
+    routes.iter().all(|(date, routes)| {
+        let fetched = |leg| fetched(*date, leg);
+        let succeeded = routes
+            .iter()
+            .filter(|r| r.legs.iter().all(|l| fetched(l) == Some(true)))
+            .count();
+        let pending = routes.iter().any(|r| {
+            r.legs.iter().all(|l| fetched(l) != Some(false))
+                && r.legs.iter().any(|l| fetched(l).is_none())
+        });
+        succeeded >= quorum || !pending
+    })
+}
+
+pub struct RouteDescription<'a> {
+    route: &'a Route,
+    providers: &'a [Box<dyn Provider>],
+}
+
+impl fmt::Display for RouteDescription<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(first) = self.route.legs.first() {
+            write!(f, "{}", first.base)?;
+        }
+        for leg in self.route.legs.iter() {
+            write!(
+                f,
+                " -({})-> {}",
+                self.providers[leg.provider].get_name(),
+                leg.quote
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fetch_legs, Graph, Leg, Route, Wait, MAX_CONCURRENT_REQUESTS, MAX_LEGS};
+    use crate::currency::Symbol;
+    use crate::providers::error::ProviderError;
+    use crate::providers::http::is_timeout;
+    use crate::providers::provider::{FetchedRates, Provider, Rates};
+
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use chrono::NaiveDate;
+    use reqwest::Url;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::delay_for;
+
+    struct MockProvider {
+        name: &'static str,
+        symbols: Vec<Symbol>,
+        rate: Decimal,
+        requests: Arc<AtomicUsize>,
+        /// Never answers requests for several quotes
+        hangs: bool,
+    }
+
+    #[async_trait]
+    impl Provider for MockProvider {
+        fn get_name(&self) -> String {
+            String::from(self.name)
+        }
+        fn supports(&self, symbol: &Symbol) -> bool {
+            self.symbols.contains(symbol)
+        }
+        fn build_url(
+            &self,
+            _base: &Symbol,
+            _quote: &Symbol,
+            _date: Option<NaiveDate>,
+        ) -> Result<Url, ProviderError> {
+            Err(ProviderError::Other(anyhow!("not needed")))
+        }
+        fn parse_rate_from_response(
+            &self,
+            _quote: &Symbol,
+            _response: &str,
+        ) -> Result<Decimal, ProviderError> {
+            Err(ProviderError::Other(anyhow!("not needed")))
+        }
+        async fn get_rate(
+            &self,
+            _base: Symbol,
+            _quote: Symbol,
+            _date: Option<NaiveDate>,
+        ) -> Result<Decimal, ProviderError> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            Ok(self.rate)
+        }
+        async fn get_rates(
+            &self,
+            _base: Symbol,
+            quotes: &[Symbol],
+            _date: Option<NaiveDate>,
+        ) -> Result<FetchedRates, ProviderError> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            if self.hangs {
+                futures::future::pending::<()>().await;
+            }
+            Ok(quotes
+                .iter()
+                .map(|q| (*q, self.rate))
+                .collect::<Rates>()
+                .into())
+        }
+    }
+
+    /// Serves rates of all quotes but ETH one by one, like most APIs
+    struct RateLimitedProvider;
+
+    #[async_trait]
+    impl Provider for RateLimitedProvider {
+        fn get_name(&self) -> String {
+            String::from("limited")
+        }
+        fn supports(&self, _symbol: &Symbol) -> bool {
+            true
+        }
+        fn build_url(
+            &self,
+            _base: &Symbol,
+            _quote: &Symbol,
+            _date: Option<NaiveDate>,
+        ) -> Result<Url, ProviderError> {
+            Err(ProviderError::Other(anyhow!("not needed")))
+        }
+        fn parse_rate_from_response(
+            &self,
+            _quote: &Symbol,
+            _response: &str,
+        ) -> Result<Decimal, ProviderError> {
+            Err(ProviderError::Other(anyhow!("not needed")))
+        }
+        async fn get_rate(
+            &self,
+            _base: Symbol,
+            quote: Symbol,
+            _date: Option<NaiveDate>,
+        ) -> Result<Decimal, ProviderError> {
+            match quote.as_str() {
+                "ETH" => Err(ProviderError::RateLimited(String::from(
+                    "Too many requests",
+                ))),
+                _ => Ok(Decimal::new(2, 0)),
+            }
+        }
+    }
+
+    /// Takes a while to answer, counting the requests answered at the same time
+    struct SlowProvider {
+        active: AtomicUsize,
+        most_active: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Provider for SlowProvider {
+        fn get_name(&self) -> String {
+            String::from("slow")
+        }
+        fn supports(&self, _symbol: &Symbol) -> bool {
+            true
+        }
+        fn build_url(
+            &self,
+            _base: &Symbol,
+            _quote: &Symbol,
+            _date: Option<NaiveDate>,
+        ) -> Result<Url, ProviderError> {
+            Err(ProviderError::Other(anyhow!("not needed")))
+        }
+        fn parse_rate_from_response(
+            &self,
+            _quote: &Symbol,
+            _response: &str,
+        ) -> Result<Decimal, ProviderError> {
+            Err(ProviderError::Other(anyhow!("not needed")))
+        }
+        async fn get_rate(
+            &self,
+            _base: Symbol,
+            _quote: Symbol,
+            _date: Option<NaiveDate>,
+        ) -> Result<Decimal, ProviderError> {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_active.fetch_max(active, Ordering::SeqCst);
+            delay_for(Duration::from_millis(5)).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            Ok(Decimal::new(2, 0))
+        }
+    }
+
+    fn providers() -> Vec<Box<dyn Provider>> {
+        counted_providers(Arc::new(AtomicUsize::new(0)))
+    }
+
+    /// Providers counting their requests in `requests`
+    fn counted_providers(requests: Arc<AtomicUsize>) -> Vec<Box<dyn Provider>> {
+        vec![
+            Box::new(MockProvider {
+                name: "fiat",
+                symbols: vec![sym("TRY"), sym("USD"), sym("EUR")],
+                rate: Decimal::new(2, 0),
+                requests: requests.clone(),
+                hangs: false,
+            }),
+            Box::new(MockProvider {
+                name: "crypto",
+                symbols: vec![sym("USD"), sym("ETH")],
+                rate: Decimal::new(3, 0),
+                requests,
+                hangs: false,
+            }),
+        ]
+    }
+
+    fn sym(code: &str) -> Symbol {
+        Symbol::from_str(code).unwrap()
+    }
+
+    fn symbols() -> Vec<Symbol> {
+        vec![sym("TRY"), sym("USD"), sym("EUR"), sym("ETH")]
+    }
+
+    #[test]
+    fn finds_direct_route() {
+        let providers = providers();
+        let graph = Graph::new(&providers, &symbols());
+
+        let routes = graph.find_routes(sym("TRY"), sym("USD"), MAX_LEGS);
+
+        assert_eq!(
+            routes,
+            vec![Route {
+                legs: vec![Leg {
+                    provider: 0,
+                    base: sym("TRY"),
+                    quote: sym("USD")
+                }]
+            }]
+        );
+    }
+
+    #[test]
+    fn finds_route_across_providers() {
+        let providers = providers();
+        let graph = Graph::new(&providers, &symbols());
+
+        let routes = graph.find_routes(sym("TRY"), sym("ETH"), MAX_LEGS);
+
+        assert_eq!(routes.len(), 1);
+        assert_eq!(
+            routes[0].describe(&providers).to_string(),
+            "TRY -(fiat)-> USD -(crypto)-> ETH"
+        );
+    }
+
+    #[test]
+    fn finds_no_route_for_unsupported_symbol() {
+        let providers = providers();
+        let graph = Graph::new(&providers, &symbols());
+
+        let routes = graph.find_routes(sym("TRY"), sym("BTC"), MAX_LEGS);
+
+        assert!(routes.is_empty());
+    }
+
+    #[test]
+    fn respects_max_legs() {
+        let providers = providers();
+        let graph = Graph::new(&providers, &symbols());
+
+        let routes = graph.find_routes(sym("TRY"), sym("ETH"), 1);
+
+        assert!(routes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn multiplies_rates_along_route() {
+        let providers = providers();
+        let graph = Graph::new(&providers, &symbols());
+        let route = graph
+            .find_routes(sym("TRY"), sym("ETH"), MAX_LEGS)
+            .pop()
+            .unwrap();
+
+        let rate = route.get_rate(&providers, None).await.ok();
+
+        assert_eq!(rate, Some(Decimal::new(6, 0)));
+    }
+
+    #[tokio::test]
+    async fn fetches_legs_of_same_base_in_one_request() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let providers = counted_providers(requests.clone());
+        let graph = Graph::new(&providers, &symbols());
+        let routes = [sym("USD"), sym("EUR")]
+            .iter()
+            .map(|q| graph.find_routes(sym("TRY"), *q, MAX_LEGS))
+            .collect::<Vec<_>>();
+
+        let rates = fetch_legs(
+            &routes
+                .iter()
+                .map(|r| (None, r.as_slice()))
+                .collect::<Vec<_>>(),
+            &providers,
+            false,
+            Wait::default(),
+        )
+        .await
+        .remove(&None)
+        .unwrap();
+        let rate = routes[1][0].rate_from(&rates).ok();
+
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rate, Some(Decimal::new(2, 0)));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_legs_pending_after_deadline() {
+        let mut providers = providers();
+        providers[1] = Box::new(MockProvider {
+            name: "crypto",
+            symbols: vec![sym("USD"), sym("ETH")],
+            rate: Decimal::new(3, 0),
+            requests: Arc::new(AtomicUsize::new(0)),
+            hangs: true,
+        });
+        let graph = Graph::new(&providers, &symbols());
+        let routes = [sym("USD"), sym("ETH")]
+            .iter()
+            .map(|q| graph.find_routes(sym("TRY"), *q, MAX_LEGS))
+            .collect::<Vec<_>>();
+
+        let rates = fetch_legs(
+            &routes
+                .iter()
+                .map(|r| (None, r.as_slice()))
+                .collect::<Vec<_>>(),
+            &providers,
+            false,
+            Wait {
+                deadline: Some(Duration::from_millis(10)),
+                quorum: None,
+            },
+        )
+        .await
+        .remove(&None)
+        .unwrap();
+
+        assert_eq!(
+            routes[0][0].rate_from(&rates).ok(),
+            Some(Decimal::new(2, 0))
+        );
+        assert!(is_timeout(&routes[1][0].rate_from(&rates).unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn completes_routes_of_every_quote_before_quorum() {
+        let providers = providers();
+        let graph = Graph::new(&providers, &symbols());
+        let routes = [sym("USD"), sym("ETH")]
+            .iter()
+            .map(|q| graph.find_routes(sym("TRY"), *q, MAX_LEGS))
+            .collect::<Vec<_>>();
+
+        let rates = fetch_legs(
+            &routes
+                .iter()
+                .map(|r| (None, r.as_slice()))
+                .collect::<Vec<_>>(),
+            &providers,
+            false,
+            Wait {
+                deadline: None,
+                quorum: Some(1),
+            },
+        )
+        .await
+        .remove(&None)
+        .unwrap();
+
+        assert_eq!(routes[1][0].legs.len(), 2);
+        assert_eq!(
+            routes[0][0].rate_from(&rates).ok(),
+            Some(Decimal::new(2, 0))
+        );
+        assert_eq!(
+            routes[1][0].rate_from(&rates).ok(),
+            Some(Decimal::new(6, 0))
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_provider_errors_of_failed_quotes() {
+        let providers: Vec<Box<dyn Provider>> = vec![Box::new(RateLimitedProvider)];
+        let graph = Graph::new(&providers, &symbols());
+        let routes = [sym("USD"), sym("ETH")]
+            .iter()
+            .map(|q| graph.find_routes(sym("TRY"), *q, 1))
+            .collect::<Vec<_>>();
+
+        let rates = fetch_legs(
+            &routes
+                .iter()
+                .map(|r| (None, r.as_slice()))
+                .collect::<Vec<_>>(),
+            &providers,
+            false,
+            Wait::default(),
+        )
+        .await
+        .remove(&None)
+        .unwrap();
+        let error = routes[1][0].rate_from(&rates).unwrap_err();
+
+        assert_eq!(
+            routes[0][0].rate_from(&rates).ok(),
+            Some(Decimal::new(2, 0))
+        );
+        assert!(matches!(
+            ProviderError::find(&error),
+            Some(ProviderError::RateLimited(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn limits_concurrent_requests() {
+        let most_active = Arc::new(AtomicUsize::new(0));
+        let providers: Vec<Box<dyn Provider>> = vec![Box::new(SlowProvider {
+            active: AtomicUsize::new(0),
+            most_active: most_active.clone(),
+        })];
+        let graph = Graph::new(&providers, &symbols());
+        let routes = graph.find_routes(sym("TRY"), sym("USD"), 1);
+        let dates = (1..=3 * MAX_CONCURRENT_REQUESTS as u32)
+            .map(|day| NaiveDate::from_ymd_opt(2020, 3, day))
+            .collect::<Vec<_>>();
+
+        let rates = fetch_legs(
+            &dates
+                .iter()
+                .chain(dates.iter())
+                .map(|date| (*date, routes.as_slice()))
+                .collect::<Vec<_>>(),
+            &providers,
+            false,
+            Wait::default(),
+        )
+        .await;
+
+        assert_eq!(rates.len(), dates.len());
+        assert!(rates
+            .values()
+            .all(|rates| routes[0].rate_from(rates).is_ok()));
+        assert_eq!(most_active.load(Ordering::SeqCst), MAX_CONCURRENT_REQUESTS);
+    }
+}
@@ -0,0 +1,156 @@
+use chrono::NaiveDate;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use std::collections::BTreeMap;
+
+use crate::providers::provider::Series;
+use crate::utils::Stats;
+
+const SPARKS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Averages the series of several providers day by day.
+pub fn merge(series: &[Series]) -> Series {
+    let mut days = BTreeMap::<NaiveDate, Vec<Decimal>>::new();
+    for (date, rate) in series.iter().flatten() {
+        days.entry(*date).or_default().push(*rate);
+    }
+
+    days.into_iter()
+        .filter_map(|(date, rates)| rates.mean().map(|rate| (date, rate)))
+        .collect()
+}
+
+/// Summary numbers of a series. Extremes are reported with the first day they occurred.
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub first: (NaiveDate, Decimal),
+    pub last: (NaiveDate, Decimal),
+    pub min: (NaiveDate, Decimal),
+    pub max: (NaiveDate, Decimal),
+    pub mean: Decimal,
+}
+
+impl Summary {
+    pub fn of(series: &Series) -> Option<Summary> {
+        let find = |rate: Decimal| {
+            series
+                .iter()
+                .find(|(_, r)| **r == rate)
+                .map(|(d, r)| (*d, *r))
+        };
+
+        Some(Summary {
+            first: series.iter().next().map(|(d, r)| (*d, *r))?,
+            last: series.iter().next_back().map(|(d, r)| (*d, *r))?,
+            min: find(series.values().minimum()?)?,
+            max: find(series.values().maximum()?)?,
+            mean: series.values().mean()?,
+        })
+    }
+
+    pub fn change(&self) -> Decimal {
+        self.last.1 - self.first.1
+    }
+
+    /// Change relative to the first rate in percent
+    pub fn change_percent(&self) -> Option<Decimal> {
+        match self.first.1.is_zero() {
+            true => None,
+            _ => Some(self.change() / self.first.1 * Decimal::new(100, 0)),
+        }
+    }
+}
+
+/// Renders rates as a line of at most `width` block characters. Longer series are
+/// averaged in buckets of consecutive days.
+pub fn sparkline(rates: &[Decimal], width: usize) -> String {
+    if rates.is_empty() || width == 0 {
+        return String::new();
+    }
+
+    let buckets = rates
+        .chunks(rates.len().div_ceil(width))
+        .filter_map(|c| c.mean())
+        .collect::<Vec<_>>();
+    let (min, max) = match (buckets.minimum(), buckets.maximum()) {
+        (Some(min), Some(max)) => (min, max),
+        _ => return String::new(),
+    };
+    let top = SPARKS.len() - 1;
+
+    buckets
+        .iter()
+        .map(|rate| match (max - min).is_zero() {
+            true => SPARKS[0],
+            _ => {
+                let level = ((rate - min) / (max - min) * Decimal::from(top)).round();
+                SPARKS[level.to_usize().unwrap_or(0).min(top)]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, sparkline, Summary};
+    use crate::providers::provider::Series;
+
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 1, d).unwrap()
+    }
+
+    fn series(rates: &[(u32, i64)]) -> Series {
+        rates
+            .iter()
+            .map(|(d, r)| (day(*d), Decimal::new(*r, 0)))
+            .collect()
+    }
+
+    #[test]
+    fn merges_series_day_by_day() {
+        let merged = merge(&[series(&[(1, 1), (2, 2)]), series(&[(2, 4), (3, 3)])]);
+
+        assert_eq!(merged, series(&[(1, 1), (2, 3), (3, 3)]));
+    }
+
+    #[test]
+    fn summarizes_series() {
+        let summary = Summary::of(&series(&[(1, 4), (2, 2), (3, 6), (4, 2), (5, 6)])).unwrap();
+
+        assert_eq!(summary.min, (day(2), Decimal::new(2, 0)));
+        assert_eq!(summary.max, (day(3), Decimal::new(6, 0)));
+        assert_eq!(summary.mean, Decimal::new(4, 0));
+        assert_eq!(summary.change(), Decimal::new(2, 0));
+        assert_eq!(summary.change_percent(), Some(Decimal::new(50, 0)));
+    }
+
+    #[test]
+    fn summary_fails_on_empty_series() {
+        assert!(Summary::of(&Series::new()).is_none());
+    }
+
+    #[test]
+    fn renders_sparkline() {
+        let rates = [0, 1, 2, 3, 4, 5, 6, 7].map(|r| Decimal::new(r, 0));
+
+        assert_eq!(sparkline(&rates, 10), "▁▂▃▄▅▆▇█");
+    }
+
+    #[test]
+    fn renders_sparkline_of_constant_rates() {
+        let rates = [Decimal::new(1, 0); 3];
+
+        assert_eq!(sparkline(&rates, 10), "▁▁▁");
+    }
+
+    #[test]
+    fn averages_long_series_into_width() {
+        let rates = [0, 0, 7, 7].map(|r| Decimal::new(r, 0));
+
+        assert_eq!(sparkline(&rates, 2), "▁█");
+    }
+}
@@ -1,14 +1,109 @@
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
 
+/// Number of values left out at either end of `len` values by a trimmed mean. At least
+/// one value is always kept.
+pub fn trim_count(len: usize, fraction: Decimal) -> usize {
+    (Decimal::from(len) * fraction)
+        .floor()
+        .to_usize()
+        .unwrap_or(0)
+        .min(len.saturating_sub(1) / 2)
+}
+
 pub trait Stats {
     fn mean(self) -> Option<Decimal>;
 
+    fn minimum(self) -> Option<Decimal>;
+
+    fn maximum(self) -> Option<Decimal>;
+
+    fn median(self) -> Option<Decimal>;
+
+    /// Mean of the values left after dropping `fraction` of them at either end.
+    fn trimmed_mean(self, fraction: Decimal) -> Option<Decimal>;
+
+    /// Mean with every value counted by the weight at its position.
+    fn weighted_mean(self, weights: &[Decimal]) -> Option<Decimal>;
+
+    /// Median of the absolute deviations from the median, a spread measure that is
+    /// robust against outliers.
+    fn median_absolute_deviation(self) -> Option<Decimal>;
+
+    /// Difference of the maximum and the minimum
+    fn range(self) -> Option<Decimal>;
+
+    /// Population variance, for values that are all there is, like the rates of all
+    /// routes
     fn variance(self) -> Option<Decimal>;
 
-    // FIXME: Decimal does currently not support sqrt() but hopefully in the future
-    // (https://github.com/paupino/rust-decimal/issues/169). Working with f64 for now.
-    fn std_deviation(self) -> Option<f64>;
+    /// Variance of a sample of a larger population, which needs at least two values
+    fn sample_variance(self) -> Option<Decimal>;
+
+    fn std_deviation(self) -> Option<Decimal>;
+
+    fn sample_std_deviation(self) -> Option<Decimal>;
+
+    /// Standard deviation relative to the mean
+    fn coefficient_of_variation(self) -> Option<Decimal>;
+
+    /// Range relative to the mean in basis points, i.e. hundredths of a percent
+    fn spread_bps(self) -> Option<Decimal>;
+}
+
+/// Decimal places of computed square roots
+pub const SQRT_PRECISION: u32 = 16;
+const SQRT_MAX_ITERATIONS: usize = 100;
+
+/// Square root by Newton's iteration, rounded to `precision` decimal places. Fails on
+/// negative input.
+pub fn sqrt(value: Decimal, precision: u32) -> Option<Decimal> {
+    if value.is_sign_negative() && !value.is_zero() {
+        return None;
+    }
+    if value.is_zero() {
+        return Some(Decimal::new(0, 0));
+    }
+
+    // Starting above the root, the iteration decreases monotonically
+    let two = Decimal::new(2, 0);
+    let tolerance = Decimal::new(1, precision.min(27));
+    let mut root = match value > Decimal::new(1, 0) {
+        true => value / two + Decimal::new(1, 0),
+        _ => Decimal::new(1, 0),
+    };
+
+    for _ in 0..SQRT_MAX_ITERATIONS {
+        let next = (root + value.checked_div(root)?) / two;
+        let done = (root - next).abs() <= tolerance;
+        root = next;
+        if done {
+            break;
+        }
+    }
+
+    Some(root.round_dp(precision).normalize())
+}
+
+/// Count, mean and sum of squared deviations from the mean, computed in a single
+/// pass by Welford's method. Unlike E[x²] - E[x]², it does not lose precision by
+/// subtracting large, close numbers.
+fn welford<'a>(values: impl IntoIterator<Item = &'a Decimal>) -> Option<(i64, Decimal, Decimal)> {
+    let mut count = 0;
+    let mut mean = Decimal::new(0, 0);
+    let mut squares = Decimal::new(0, 0);
+
+    for x in values.into_iter() {
+        count += 1;
+        let delta = x - mean;
+        mean += delta / Decimal::new(count, 0);
+        squares += delta * (x - mean);
+    }
+
+    match count {
+        0 => None,
+        _ => Some((count, mean, squares)),
+    }
 }
 
 impl<'a, I: IntoIterator<Item = &'a Decimal>> Stats for I {
@@ -28,38 +123,102 @@ impl<'a, I: IntoIterator<Item = &'a Decimal>> Stats for I {
         }
     }
 
-    fn variance(self) -> Option<Decimal> {
-        let mut count = 0;
+    fn minimum(self) -> Option<Decimal> {
+        self.into_iter().min().cloned()
+    }
+
+    fn maximum(self) -> Option<Decimal> {
+        self.into_iter().max().cloned()
+    }
+
+    fn median(self) -> Option<Decimal> {
+        let mut values = self.into_iter().cloned().collect::<Vec<_>>();
+        values.sort();
+
+        let middle = values.len() / 2;
+        match values.len() {
+            0 => None,
+            n if n % 2 == 0 => Some((values[middle - 1] + values[middle]) / Decimal::new(2, 0)),
+            _ => Some(values[middle]),
+        }
+    }
+
+    fn trimmed_mean(self, fraction: Decimal) -> Option<Decimal> {
+        let mut values = self.into_iter().cloned().collect::<Vec<_>>();
+        values.sort();
+
+        let trimmed = trim_count(values.len(), fraction);
+        values[trimmed..values.len() - trimmed].iter().mean()
+    }
+
+    fn weighted_mean(self, weights: &[Decimal]) -> Option<Decimal> {
         let mut total = Decimal::new(0, 0);
-        let mut totalsq = Decimal::new(0, 0);
+        let mut total_weight = Decimal::new(0, 0);
 
-        for x in self.into_iter() {
-            count += 1;
-            total += x;
-            totalsq += x * x;
+        for (x, weight) in self.into_iter().zip(weights) {
+            total += x * weight;
+            total_weight += weight;
         }
 
-        if count == 0 {
-            return None;
+        match total_weight.is_zero() {
+            true => None,
+            _ => Some(total / total_weight),
         }
+    }
+
+    fn median_absolute_deviation(self) -> Option<Decimal> {
+        let values = self.into_iter().collect::<Vec<_>>();
+        let median = values.iter().cloned().median()?;
+
+        values
+            .iter()
+            .map(|x| (*x - median).abs())
+            .collect::<Vec<_>>()
+            .median()
+    }
+
+    fn range(self) -> Option<Decimal> {
+        let values = self.into_iter().collect::<Vec<_>>();
+        Some(values.iter().cloned().maximum()? - values.iter().cloned().minimum()?)
+    }
+
+    fn variance(self) -> Option<Decimal> {
+        let (count, _, squares) = welford(self)?;
+        Some(squares / Decimal::new(count, 0))
+    }
 
-        let count = Decimal::new(count, 0);
+    fn sample_variance(self) -> Option<Decimal> {
+        match welford(self)? {
+            (count, _, squares) if count > 1 => Some(squares / Decimal::new(count - 1, 0)),
+            _ => None,
+        }
+    }
 
-        let mean = total / count;
-        let meansq = totalsq / count;
+    fn std_deviation(self) -> Option<Decimal> {
+        sqrt(self.variance()?, SQRT_PRECISION)
+    }
+
+    fn sample_std_deviation(self) -> Option<Decimal> {
+        sqrt(self.sample_variance()?, SQRT_PRECISION)
+    }
 
-        let variance = meansq - (mean * mean);
-        Some(variance.abs())
+    fn coefficient_of_variation(self) -> Option<Decimal> {
+        let (count, mean, squares) = welford(self)?;
+        let std_deviation = sqrt(squares / Decimal::new(count, 0), SQRT_PRECISION)?;
+        std_deviation.checked_div(mean.abs())
     }
 
-    fn std_deviation(self) -> Option<f64> {
-        Some(self.variance()?.to_f64()?.sqrt())
+    fn spread_bps(self) -> Option<Decimal> {
+        let values = self.into_iter().collect::<Vec<_>>();
+        let range = values.iter().cloned().range()?;
+        let mean = values.iter().cloned().mean()?;
+        (range * Decimal::new(10_000, 0)).checked_div(mean.abs())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Stats;
+    use super::{sqrt, Stats};
     use rust_decimal::Decimal;
 
     #[test]
@@ -84,6 +243,81 @@ mod tests {
         assert!(average.is_none());
     }
 
+    #[test]
+    fn computes_minimum_and_maximum_of_decimals() {
+        let decimals = vec![Decimal::new(2, 0), Decimal::new(1, 0), Decimal::new(3, 0)];
+
+        assert_eq!(decimals.minimum(), Some(Decimal::new(1, 0)));
+        assert_eq!(decimals.maximum(), Some(Decimal::new(3, 0)));
+    }
+
+    #[test]
+    fn minimum_fails_on_empty_data() {
+        let decimals: Vec<Decimal> = vec![];
+
+        assert!(decimals.minimum().is_none());
+    }
+
+    #[test]
+    fn computes_median_of_odd_and_even_counts() {
+        let odd = vec![Decimal::new(3, 0), Decimal::new(1, 0), Decimal::new(2, 0)];
+        let even = vec![
+            Decimal::new(4, 0),
+            Decimal::new(1, 0),
+            Decimal::new(2, 0),
+            Decimal::new(3, 0),
+        ];
+
+        assert_eq!(odd.median(), Some(Decimal::new(2, 0)));
+        assert_eq!(even.median(), Some(Decimal::new(25, 1)));
+        assert!(Vec::<Decimal>::new().median().is_none());
+    }
+
+    #[test]
+    fn computes_trimmed_mean_of_decimals() {
+        let decimals = (1..=9)
+            .map(|i| Decimal::new(i, 0))
+            .chain(Some(Decimal::new(100, 0)))
+            .collect::<Vec<_>>();
+
+        let trimmed_mean = decimals.trimmed_mean(Decimal::new(1, 1));
+
+        assert_eq!(trimmed_mean, Some(Decimal::new(55, 1)));
+    }
+
+    #[test]
+    fn trimmed_mean_keeps_middle_of_few_values() {
+        let decimals = vec![Decimal::new(1, 0), Decimal::new(3, 0)];
+
+        let trimmed_mean = decimals.trimmed_mean(Decimal::new(5, 1));
+
+        assert_eq!(trimmed_mean, Some(Decimal::new(2, 0)));
+    }
+
+    #[test]
+    fn computes_weighted_mean_of_decimals() {
+        let decimals = vec![Decimal::new(1, 0), Decimal::new(4, 0)];
+        let weights = vec![Decimal::new(2, 0), Decimal::new(1, 0)];
+
+        assert_eq!(decimals.weighted_mean(&weights), Some(Decimal::new(2, 0)));
+        assert!(decimals.weighted_mean(&[Decimal::new(0, 0); 2]).is_none());
+    }
+
+    #[test]
+    fn computes_median_absolute_deviation_of_decimals() {
+        let decimals = vec![
+            Decimal::new(1, 0),
+            Decimal::new(2, 0),
+            Decimal::new(3, 0),
+            Decimal::new(100, 0),
+        ];
+
+        assert_eq!(
+            decimals.median_absolute_deviation(),
+            Some(Decimal::new(1, 0))
+        );
+    }
+
     #[test]
     fn computes_variance_of_decimals() {
         let decimals = vec![Decimal::new(1, 0), Decimal::new(2, 0)];
@@ -118,11 +352,72 @@ mod tests {
 
         assert_eq!(
             std_deviation,
-            Some(0.5),
+            Some(Decimal::new(5, 1)),
             "The standard deviation of [1,2] should be 0.5"
         );
     }
 
+    #[test]
+    fn computes_square_roots() {
+        assert_eq!(sqrt(Decimal::new(16, 0), 10), Some(Decimal::new(4, 0)));
+        assert_eq!(sqrt(Decimal::new(25, 4), 10), Some(Decimal::new(5, 2)));
+        assert_eq!(
+            sqrt(Decimal::new(2, 0), 16),
+            Some(Decimal::new(14142135623730950, 16))
+        );
+        assert_eq!(sqrt(Decimal::new(0, 0), 10), Some(Decimal::new(0, 0)));
+        assert!(sqrt(Decimal::new(-1, 0), 10).is_none());
+    }
+
+    #[test]
+    fn computes_square_roots_of_large_values() {
+        let value = Decimal::new(i64::MAX, 0) * Decimal::new(1_000_000_000, 0);
+
+        let root = sqrt(value, 4).unwrap();
+
+        assert!((root * root - value).abs() / value < Decimal::new(1, 12));
+    }
+
+    #[test]
+    fn computes_sample_variance_of_decimals() {
+        let decimals = vec![Decimal::new(1, 0), Decimal::new(2, 0), Decimal::new(3, 0)];
+
+        assert_eq!(decimals.sample_variance(), Some(Decimal::new(1, 0)));
+        assert_eq!(decimals.sample_std_deviation(), Some(Decimal::new(1, 0)));
+        assert!(vec![Decimal::new(1, 0)].sample_variance().is_none());
+    }
+
+    #[test]
+    fn computes_variance_of_close_large_values() {
+        // E[x²] - E[x]² loses the small differences next to the large values
+        let decimals = vec![
+            Decimal::new(1_000_000_000_001, 0),
+            Decimal::new(1_000_000_000_003, 0),
+        ];
+
+        assert_eq!(decimals.variance(), Some(Decimal::new(1, 0)));
+    }
+
+    #[test]
+    fn computes_range_and_relative_spread() {
+        let decimals = vec![Decimal::new(99, 2), Decimal::new(101, 2)];
+
+        assert_eq!(decimals.range(), Some(Decimal::new(2, 2)));
+        assert_eq!(decimals.spread_bps(), Some(Decimal::new(200, 0)));
+        assert_eq!(
+            decimals.coefficient_of_variation(),
+            Some(Decimal::new(1, 2))
+        );
+    }
+
+    #[test]
+    fn relative_measures_fail_on_zero_mean() {
+        let decimals = vec![Decimal::new(-1, 0), Decimal::new(1, 0)];
+
+        assert!(decimals.spread_bps().is_none());
+        assert!(decimals.coefficient_of_variation().is_none());
+    }
+
     #[test]
     fn std_deviation_fails_on_empty_data() {
         let decimals = vec![];